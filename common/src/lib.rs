@@ -1,4 +1,5 @@
 pub mod message;
+pub mod mode;
 pub mod parse;
 // pub mod stream_handler;
 
@@ -22,7 +22,7 @@ pub enum Command {
     USER { username: String, _1: (), _2: (), realname: String },
     PING { token: String },
     PONG { server: String, token: String },
-    // OPER
+    OPER { name: String, password: String },
     QUIT { reason: String },
     ERROR { reason: String },
 
@@ -30,10 +30,11 @@ pub enum Command {
     JOIN { channels: Vec<String>, keys: Vec<String>, alt: bool },
     PART { channels: Vec<String>, reason: String },
     TOPIC { channel: String, topic: Option<String> },
-    // NAMES
+    NAMES { channels: Vec<String> },
     LIST { channels: Vec<String>, elistconds: Option<String> },
-    // INVITE
-    // KICK
+    /// Both are empty to list the channels the user is invited to.
+    INVITE { nickname: String, channel: String },
+    KICK { channel: String, users: Vec<String>, reason: String },
 
     /* Server Queries and Commands */
     MOTD { target: String },
@@ -49,23 +50,29 @@ pub enum Command {
 
     /* Sending Messages */
     PRIVMSG { targets: Vec<String>, text: String },
-    // NOTICE
+    NOTICE { targets: Vec<String>, text: String },
 
     /* User Based Queries */
-    WHO { mask: String },
-    // WHOIS
-    // WHOWAS
+    /// `fields` is set for WHOX (`%fields,token`), with `token` defaulting to "0".
+    /// Without WHOX `token` is empty.
+    WHO { mask: String, opers_only: bool, fields: Option<String>, token: String },
+    /// `target` is the server to ask, or empty.
+    WHOIS { target: String, nicks: Vec<String> },
+    /// `count` is None for all entries.
+    WHOWAS { nick: String, count: Option<usize> },
 
     /* Operator Messages */
     // KILL
-    // REHASH
+    REHASH,
     // RESTART
     // SQUIT
 
     /* Optional Messages */
-    // AWAY
+    /// An empty text marks the user as no longer away.
+    AWAY { text: String },
+    ISON { nicknames: Vec<String> },
     // LINKS
-    // USERHOST
+    USERHOST { nicknames: Vec<String> },
     // WALLOPS
 
     /* Non client messages */
@@ -79,10 +86,47 @@ pub enum Command {
     Raw(String),
 }
 
+/// Maximum length of a line in either direction, including the trailing CRLF.
+pub const MAX_LINE_LEN: usize = 512;
+
 impl Message {
     pub fn new(prefix: Option<&str>, command: Command) -> Self {
         Message { prefix: prefix.map(|s| s.to_string()), command }
     }
+
+    /// The message as it is sent, with its CRLF.
+    /// Lines over MAX_LINE_LEN are truncated without splitting a UTF-8 character.
+    pub fn to_line(&self) -> String {
+        let mut line = self.to_string();
+        let mut len = line.len().min(MAX_LINE_LEN - 2);
+        while !line.is_char_boundary(len) {
+            len -= 1;
+        }
+        line.truncate(len);
+        line.push_str("\r\n");
+        line
+    }
+}
+
+/// Joins `items` with spaces into as few strings as possible, each at most `room` bytes
+/// and `max_items` items long. An item longer than `room` gets a string of its own.
+pub fn pack_list(items: &[String], room: usize, max_items: usize) -> Vec<String> {
+    let mut packed: Vec<String> = Vec::new();
+    let mut count = 0;
+    for item in items {
+        match packed.last_mut() {
+            Some(last) if count < max_items && last.len() + 1 + item.len() <= room => {
+                last.push(' ');
+                last.push_str(item);
+                count += 1;
+            }
+            _ => {
+                packed.push(item.clone());
+                count = 1;
+            }
+        }
+    }
+    packed
 }
 
 #[allow(non_camel_case_types)]
@@ -97,41 +141,90 @@ pub enum Numeric {
     RPL_ISUPPORT = 5,
     // Command Replies 200 ~ 399
     RPL_UMODEIS = 221,
+    RPL_AWAY = 301,
+    RPL_USERHOST = 302,
+    RPL_ISON = 303,
+    RPL_UNAWAY = 305,
+    RPL_NOWAWAY = 306,
+    RPL_WHOISUSER = 311,
+    RPL_WHOISSERVER = 312,
+    RPL_WHOISOPERATOR = 313,
+    RPL_WHOWASUSER = 314,
     RPL_ENDOFWHO = 315,
+    RPL_WHOISIDLE = 317,
+    RPL_ENDOFWHOIS = 318,
+    RPL_WHOISCHANNELS = 319,
     RPL_LISTSTART = 321,
     RPL_LIST = 322,
     RPL_LISTEND = 323,
     RPL_CHANNELMODEIS = 324,
     RPL_CREATIONTIME = 329,
+    RPL_WHOISACCOUNT = 330,
     RPL_NOTOPIC = 331,
     RPL_TOPIC = 332,
     RPL_TOPICWHOTIME = 333,
+    RPL_INVITELIST = 336,
+    RPL_ENDOFINVITELIST = 337,
+    RPL_INVITING = 341,
+    RPL_INVEXLIST = 346,
+    RPL_ENDOFINVEXLIST = 347,
+    RPL_EXCEPTLIST = 348,
+    RPL_ENDOFEXCEPTLIST = 349,
     RPL_WHOREPLY = 352,
     RPL_NAMREPLY = 353,
+    RPL_WHOSPCRPL = 354,
     RPL_ENDOFNAMES = 366,
+    RPL_BANLIST = 367,
+    RPL_ENDOFBANLIST = 368,
+    RPL_ENDOFWHOWAS = 369,
     RPL_MOTD = 372,
     RPL_MOTDSTART = 375,
     RPL_ENDOFMOTD = 376,
+    RPL_YOUREOPER = 381,
+    RPL_REHASHING = 382,
 
     // Error Replies 400~509
     ERR_NOSUCHNICK = 401,
     ERR_NOSUCHSERVER = 402,
     ERR_NOSUCHCHANNEL = 403,
+    ERR_CANNOTSENDTOCHAN = 404,
+    ERR_WASNOSUCHNICK = 406,
+    ERR_TOOMANYTARGETS = 407,
     ERR_NORECIPIENT = 411,
     ERR_NOTEXTTOSEND = 412,
+    ERR_INPUTTOOLONG = 417,
     ERR_UNKNOWNCOMMAND = 421,
     ERR_NOMOTD = 422,
     ERR_NONICKNAMEGIVEN = 431,
     ERR_ERRONEUSNICKNAME = 432,
     ERR_NICKNAMEINUSE = 433,
+    ERR_BANNICKCHANGE = 435,
+    ERR_USERNOTINCHANNEL = 441,
     ERR_NOTONCHANNEL = 442,
+    ERR_USERONCHANNEL = 443,
     ERR_NEEDMOREPARAMS = 461,
     ERR_ALREADYREGISTERED = 462,
+    ERR_PASSWDMISMATCH = 464,
+    ERR_YOUREBANNEDCREEP = 465,
+    ERR_CHANNELISFULL = 471,
     ERR_UNKNOWNMODE = 472,
+    ERR_INVITEONLYCHAN = 473,
+    ERR_BANNEDFROMCHAN = 474,
+    ERR_BADCHANNELKEY = 475,
+    ERR_BANLISTFULL = 478,
+    ERR_NOPRIVILEGES = 481,
     ERR_CHANOPRIVSNEEDED = 482,
+    ERR_NOOPERHOST = 491,
 
     ERR_UMODEUNKNOWNFLAG = 501,
     ERR_USERSDONTMATCH = 502,
+    ERR_INVALIDKEY = 525,
+
+    RPL_WHOISSECURE = 671,
+
+    ERR_INVALIDMODEPARAM = 696,
+    RPL_QUIETLIST = 728,
+    RPL_ENDOFQUIETLIST = 729,
 }
 
 impl Display for Message {
@@ -165,7 +258,7 @@ impl Display for Command {
                 }
                 write!(f, " :{}", token)
             }
-            // OPER
+            OPER { name, password } => write!(f, "OPER {} {}", name, password),
             QUIT { reason } => {
                 if !reason.is_empty() {
                     write!(f, "QUIT :{}", reason)
@@ -203,7 +296,13 @@ impl Display for Command {
                 }
                 Ok(())
             }
-            // NAMES
+            NAMES { channels } => {
+                write!(f, "NAMES")?;
+                if !channels.is_empty() {
+                    write!(f, " {}", channels.join(","))?;
+                }
+                Ok(())
+            }
             LIST { channels, elistconds } => {
                 write!(f, "LIST")?;
                 if !channels.is_empty() {
@@ -214,8 +313,20 @@ impl Display for Command {
                 }
                 Ok(())
             }
-            // INVITE
-            // KICK
+            INVITE { nickname, channel } => {
+                write!(f, "INVITE")?;
+                if !nickname.is_empty() {
+                    write!(f, " {} {}", nickname, channel)?;
+                }
+                Ok(())
+            }
+            KICK { channel, users, reason } => {
+                write!(f, "KICK {} {}", channel, users.join(","))?;
+                if !reason.is_empty() {
+                    write!(f, " :{}", reason)?;
+                }
+                Ok(())
+            }
 
             /* Server Queries and Commands */
             MOTD { target } => {
@@ -239,30 +350,64 @@ impl Display for Command {
                     write!(f, " {}", modestring)?;
                 }
                 if !modeargs.is_empty() {
-                    write!(f, " {}", modeargs.join(","))?;
+                    write!(f, " {}", modeargs.join(" "))?;
                 }
                 Ok(())
             }
 
             /* Sending Messages */
             PRIVMSG { targets, text } => write!(f, "PRIVMSG {} :{}", targets.join(","), text),
-            // NOTICE
+            NOTICE { targets, text } => write!(f, "NOTICE {} :{}", targets.join(","), text),
 
             /* User Based Queries */
-            WHO { mask } => write!(f, "WHO {}", mask),
-            // WHOIS
-            // WHOWAS
+            WHO { mask, opers_only, fields, token } => {
+                write!(f, "WHO {}", mask)?;
+                if *opers_only || fields.is_some() {
+                    write!(f, " ")?;
+                }
+                if *opers_only {
+                    write!(f, "o")?;
+                }
+                if let Some(fields) = fields {
+                    write!(f, "%{}", fields)?;
+                    if !token.is_empty() {
+                        write!(f, ",{}", token)?;
+                    }
+                }
+                Ok(())
+            }
+            WHOIS { target, nicks } => {
+                write!(f, "WHOIS")?;
+                if !target.is_empty() {
+                    write!(f, " {}", target)?;
+                }
+                write!(f, " {}", nicks.join(","))
+            }
+            WHOWAS { nick, count } => {
+                write!(f, "WHOWAS {}", nick)?;
+                if let Some(count) = count {
+                    write!(f, " {}", count)?;
+                }
+                Ok(())
+            }
 
             /* Operator Messages */
             // KILL
-            // REHASH
+            REHASH => write!(f, "REHASH"),
             // RESTART
             // SQUIT
 
             /* Optional Messages */
-            // AWAY
+            AWAY { text } => {
+                if !text.is_empty() {
+                    write!(f, "AWAY :{}", text)
+                } else {
+                    write!(f, "AWAY")
+                }
+            }
+            ISON { nicknames } => write!(f, "ISON {}", nicknames.join(" ")),
             // LINKS
-            // USERHOST
+            USERHOST { nicknames } => write!(f, "USERHOST {}", nicknames.join(" ")),
             // WALLOPS
 
             /* Other */
@@ -274,3 +419,40 @@ impl Display for Command {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_line() {
+        let msg = Message::new(Some("irc.example"), Command::PING { token: "abc".to_string() });
+        assert_eq!(msg.to_line(), ":irc.example PING :abc\r\n");
+
+        // "PING :x" is 7 bytes, so a 2-byte character straddles the limit
+        let msg = Message::new(None, Command::PING { token: "x".to_string() + &"é".repeat(300) });
+        let line = msg.to_line();
+        assert_eq!(line.len(), MAX_LINE_LEN - 1);
+        assert!(line.ends_with("é\r\n"));
+    }
+
+    #[test]
+    fn test_mode_display() {
+        let command = Command::MODE {
+            target: "#chan".to_string(),
+            modestring: "+ov".to_string(),
+            modeargs: vec!["aki".to_string(), "bob".to_string()],
+        };
+        assert_eq!(command.to_string(), "MODE #chan +ov aki bob");
+    }
+
+    #[test]
+    fn test_pack_list() {
+        let items: Vec<String> = ["alpha", "beta", "gamma", "delta"].map(String::from).to_vec();
+        assert_eq!(pack_list(&items, 100, 10), vec!["alpha beta gamma delta"]);
+        assert_eq!(pack_list(&items, 10, 10), vec!["alpha beta", "gamma", "delta"]);
+        assert_eq!(pack_list(&items, 100, 3), vec!["alpha beta gamma", "delta"]);
+        assert_eq!(pack_list(&items, 3, 10), items);
+        assert!(pack_list(&[], 100, 10).is_empty());
+    }
+}
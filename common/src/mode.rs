@@ -0,0 +1,181 @@
+// Mode changes as sent in MODE, split into one change per mode letter.
+
+/// Mode letters grouped by how they take parameters, as advertised in CHANMODES and PREFIX.
+/// User modes are all flags.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModeClasses<'a> {
+    /// Type A: lists. A parameter when given; without one it is a query for the list.
+    pub a: &'a str,
+    /// Type B: a parameter when set and unset.
+    pub b: &'a str,
+    /// Type C: a parameter when set.
+    pub c: &'a str,
+    /// Type D: flags without parameters.
+    pub d: &'a str,
+    /// Prefix modes: a nick parameter when set and unset.
+    pub prefix: &'a str,
+}
+
+impl ModeClasses<'_> {
+    pub fn contains(&self, mode: char) -> bool {
+        [self.a, self.b, self.c, self.d, self.prefix].iter().any(|class| class.contains(mode))
+    }
+    fn takes_param(&self, set: bool, mode: char) -> bool {
+        self.a.contains(mode)
+            || self.b.contains(mode)
+            || self.prefix.contains(mode)
+            || set && self.c.contains(mode)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModeChange {
+    /// `+` rather than `-`
+    pub set: bool,
+    pub mode: char,
+    /// None if the mode takes no parameter here or the client left it out.
+    pub param: Option<String>,
+}
+
+impl ModeChange {
+    pub fn new(set: bool, mode: char, param: Option<&str>) -> ModeChange {
+        ModeChange { set, mode, param: param.map(str::to_string) }
+    }
+}
+
+/// Pairs each letter in `modestring` with its parameter from `params`.
+/// At most `max_params` changes get a parameter; later ones that need one are dropped.
+/// Repeated changes are merged: a mode with a single value keeps only its last change,
+/// while list and prefix modes drop exact repeats.
+/// Unknown letters are kept, without a parameter, for the caller to report.
+pub fn parse_mode_changes(
+    modestring: &str,
+    params: &[String],
+    classes: &ModeClasses,
+    max_params: usize,
+) -> Vec<ModeChange> {
+    let mut params = params.iter();
+    let mut used_params = 0;
+    let mut set = true;
+    let mut changes: Vec<ModeChange> = Vec::new();
+    for mode in modestring.chars() {
+        match mode {
+            '+' => set = true,
+            '-' => set = false,
+            _ if classes.takes_param(set, mode) && used_params >= max_params => {}
+            _ => {
+                let param =
+                    if classes.takes_param(set, mode) { params.next().cloned() } else { None };
+                used_params += param.is_some() as usize;
+                let change = ModeChange { set, mode, param };
+                if classes.a.contains(mode) || classes.prefix.contains(mode) {
+                    if !changes.iter().any(|other| same_change(other, &change)) {
+                        changes.push(change);
+                    }
+                } else {
+                    changes.retain(|other| other.mode != mode);
+                    changes.push(change);
+                }
+            }
+        }
+    }
+    changes
+}
+
+fn same_change(a: &ModeChange, b: &ModeChange) -> bool {
+    a.set == b.set
+        && a.mode == b.mode
+        && match (&a.param, &b.param) {
+            (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+            (a, b) => a == b,
+        }
+}
+
+/// The modestring and parameters for `changes`, with each sign written once per run,
+/// e.g. `+ov-k` with `nick nick key`.
+pub fn format_mode_changes(changes: &[ModeChange]) -> (String, Vec<String>) {
+    let mut modestring = String::new();
+    let mut params = Vec::new();
+    let mut sign = None;
+    for change in changes {
+        if sign != Some(change.set) {
+            modestring.push(if change.set { '+' } else { '-' });
+            sign = Some(change.set);
+        }
+        modestring.push(change.mode);
+        params.extend(change.param.clone());
+    }
+    (modestring, params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLASSES: ModeClasses = ModeClasses { a: "beI", b: "k", c: "l", d: "imnst", prefix: "ov" };
+
+    #[test]
+    fn test_parse_mode_changes() {
+        let params = ["nick".to_string(), "10".to_string(), "*!*@*".to_string()];
+        assert_eq!(
+            parse_mode_changes("+otl-nb", &params, &CLASSES, 4),
+            vec![
+                ModeChange::new(true, 'o', Some("nick")),
+                ModeChange::new(true, 't', None),
+                ModeChange::new(true, 'l', Some("10")),
+                ModeChange::new(false, 'n', None),
+                ModeChange::new(false, 'b', Some("*!*@*")),
+            ]
+        );
+        // -l takes no parameter, and a list mode without one is a query
+        assert_eq!(
+            parse_mode_changes("-l+b", &[], &CLASSES, 4),
+            vec![ModeChange::new(false, 'l', None), ModeChange::new(true, 'b', None)]
+        );
+        assert_eq!(
+            parse_mode_changes("+x", &params, &CLASSES, 4),
+            vec![ModeChange::new(true, 'x', None)]
+        );
+    }
+
+    #[test]
+    fn test_parse_mode_changes_limit() {
+        let params = ["a".to_string(), "b".to_string(), "c".to_string()];
+        assert_eq!(
+            parse_mode_changes("+vvvm", &params, &CLASSES, 2),
+            vec![
+                ModeChange::new(true, 'v', Some("a")),
+                ModeChange::new(true, 'v', Some("b")),
+                ModeChange::new(true, 'm', None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_mode_changes_merge() {
+        let params = ["a".to_string(), "A".to_string(), "b".to_string()];
+        assert_eq!(
+            parse_mode_changes("+nvv-n+v+n", &params, &CLASSES, 4),
+            vec![
+                ModeChange::new(true, 'v', Some("a")),
+                ModeChange::new(true, 'v', Some("b")),
+                ModeChange::new(true, 'n', None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_mode_changes() {
+        let changes = [
+            ModeChange::new(true, 'o', Some("nick")),
+            ModeChange::new(true, 't', None),
+            ModeChange::new(false, 'k', Some("*")),
+            ModeChange::new(false, 'n', None),
+        ];
+        assert_eq!(
+            format_mode_changes(&changes),
+            ("+ot-kn".to_string(), vec!["nick".to_string(), "*".to_string()])
+        );
+        assert_eq!(format_mode_changes(&[]), (String::new(), vec![]));
+    }
+}
@@ -38,6 +38,11 @@ pub fn parse_message(i: &str) -> Result<Message, IrcError> {
     Ok(Message { prefix, command })
 }
 
+/// Whether a channel key (`+k`) is well-formed: 1 to 23 printable characters, no spaces or commas.
+pub fn is_valid_key(key: &str) -> bool {
+    all_consuming(sub_parse::key).parse(key).is_ok()
+}
+
 fn prefix(i: &str) -> IResult<&str, &str> {
     let servername = recognize(many1(none_of(" ")));
     delimited(char(':'), servername, space).parse(i)
@@ -92,4 +97,13 @@ mod tests {
             Message::new(Some("pref"), Command::NICK { nickname: "test".to_string() })
         );
     }
+
+    #[test]
+    fn test_is_valid_key() {
+        assert!(is_valid_key("hunter2"));
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("two words"));
+        assert!(!is_valid_key("a,b"));
+        assert!(!is_valid_key(&"k".repeat(24)));
+    }
 }
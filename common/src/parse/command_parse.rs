@@ -15,7 +15,7 @@ pub fn parse_command(cmd: &str, params: &[&str]) -> Command {
         "USER" => parse_USER(params),
         "PING" => parse_PING(params),
         "PONG" => parse_PONG(params),
-        // OPER
+        "OPER" => parse_OPER(params),
         "QUIT" => parse_QUIT(params),
         // ERROR
 
@@ -23,10 +23,10 @@ pub fn parse_command(cmd: &str, params: &[&str]) -> Command {
         "JOIN" => parse_JOIN(params),
         "PART" => parse_PART(params),
         "TOPIC" => parse_TOPIC(params),
-        // NAMES
+        "NAMES" => parse_NAMES(params),
         "LIST" => parse_LIST(params),
-        // INVITE
-        // KICK
+        "INVITE" => parse_INVITE(params),
+        "KICK" => parse_KICK(params),
 
         /* Server Queries and Commands */
         "MOTD" => parse_MOTD(params),
@@ -42,23 +42,24 @@ pub fn parse_command(cmd: &str, params: &[&str]) -> Command {
 
         /* Sending Messages */
         "PRIVMSG" => parse_PRIVMSG(params),
-        // NOTICE
+        "NOTICE" => parse_NOTICE(params),
 
         /* User Based Queries */
         "WHO" => parse_WHO(params),
-        // WHOIS
-        // WHOWAS
+        "WHOIS" => parse_WHOIS(params),
+        "WHOWAS" => parse_WHOWAS(params),
 
         /* Operator Messages */
         // KILL
-        // REHASH
+        "REHASH" => Command::REHASH,
         // RESTART
         // SQUIT
 
         /* Optional Messages */
-        // AWAY
+        "AWAY" => parse_AWAY(params),
+        "ISON" => parse_ISON(params),
         // LINKS
-        // USERHOST
+        "USERHOST" => parse_USERHOST(params),
         // WALLOPS
 
         /* Other */
@@ -149,13 +150,28 @@ fn parse_PING(params: &[&str]) -> Command {
     Command::PING { token }
 }
 #[allow(non_snake_case)]
-fn parse_PONG(_params: &[&str]) -> Command {
-    // no need to parse PONG
-    let server = String::new();
-    let token = String::new();
-    Command::PONG { server, token }
+fn parse_PONG(params: &[&str]) -> Command {
+    // PONG [<server>] <token>, though a bare PONG is accepted too
+    let (server, token) = match params {
+        [] => ("", ""),
+        [token] => ("", *token),
+        [server, token, ..] => (*server, *token),
+    };
+    Command::PONG { server: server.to_owned(), token: token.to_owned() }
+}
+#[allow(non_snake_case)]
+fn parse_OPER(params: &[&str]) -> Command {
+    if params.len() < 2 {
+        return Command::Invalid(
+            "OPER".to_string(),
+            Some(ERR_NEEDMOREPARAMS),
+            "OPER :Not enough parameters".to_string(),
+        );
+    }
+    let name = params[0].to_owned();
+    let password = params[1].to_owned();
+    Command::OPER { name, password }
 }
-// OPER
 #[allow(non_snake_case)]
 fn parse_QUIT(params: &[&str]) -> Command {
     let reason = if params.is_empty() { "".to_string() } else { params[0].to_string() };
@@ -220,7 +236,15 @@ fn parse_TOPIC(params: &[&str]) -> Command {
 
     Command::TOPIC { channel, topic }
 }
-// NAMES
+#[allow(non_snake_case)]
+fn parse_NAMES(params: &[&str]) -> Command {
+    let channels = match params.first() {
+        Some(channels) => channels.split(",").map(String::from).collect(),
+        None => Vec::new(),
+    };
+
+    Command::NAMES { channels }
+}
 #[allow(non_snake_case)]
 fn parse_LIST(params: &[&str]) -> Command {
     let channels = if !params.is_empty() {
@@ -234,8 +258,35 @@ fn parse_LIST(params: &[&str]) -> Command {
 
     Command::LIST { channels, elistconds }
 }
-// INVITE
-// KICK
+#[allow(non_snake_case)]
+fn parse_INVITE(params: &[&str]) -> Command {
+    match params {
+        [] => Command::INVITE { nickname: String::new(), channel: String::new() },
+        [_] => Command::Invalid(
+            "INVITE".to_string(),
+            Some(ERR_NEEDMOREPARAMS),
+            "INVITE :Not enough parameters".to_string(),
+        ),
+        [nickname, channel, ..] => {
+            Command::INVITE { nickname: nickname.to_string(), channel: channel.to_string() }
+        }
+    }
+}
+#[allow(non_snake_case)]
+fn parse_KICK(params: &[&str]) -> Command {
+    if params.len() < 2 {
+        return Command::Invalid(
+            "KICK".to_string(),
+            Some(ERR_NEEDMOREPARAMS),
+            "KICK :Not enough parameters".to_string(),
+        );
+    }
+    let channel = params[0].to_owned();
+    let users = params[1].split(",").map(String::from).collect();
+    let reason = params.get(2).cloned().unwrap_or_default().to_string();
+
+    Command::KICK { channel, users, reason }
+}
 
 /* Server Queries and Commands */
 #[allow(non_snake_case)]
@@ -260,30 +311,12 @@ fn parse_MODE(params: &[&str]) -> Command {
             "MODE :Not enough parameters".to_string(),
         );
     }
-    let target = params[0].to_owned();
-    let mut modestring = String::new();
-    let mut modeargs: Vec<String> = Vec::new();
-    if params.len() >= 2 {
-        let mut mode = '+';
-        let mut current_mode = ' ';
-        let mut num_modes = 0;
-        for c in params[1].chars() {
-            if c == '+' || c == '-' {
-                mode = c
-            } else {
-                if current_mode != mode {
-                    modestring.push(mode);
-                    current_mode = mode;
-                }
-                modestring.push(c);
-                num_modes += 1;
-            }
-        }
-        // push remaining params into modeargs, up to # modes
-        modeargs.extend(params.iter().skip(2).take(num_modes).map(|s| s.to_string()));
+    // which modes take arguments depends on the server, see common::mode
+    Command::MODE {
+        target: params[0].to_owned(),
+        modestring: params.get(1).map(|s| s.to_string()).unwrap_or_default(),
+        modeargs: params.iter().skip(2).map(|s| s.to_string()).collect(),
     }
-
-    Command::MODE { target, modestring, modeargs }
 }
 
 /* Sending Messages */
@@ -308,7 +341,15 @@ fn parse_PRIVMSG(params: &[&str]) -> Command {
     let text = params[1].to_owned();
     Command::PRIVMSG { targets, text }
 }
-// NOTICE
+#[allow(non_snake_case)]
+fn parse_NOTICE(params: &[&str]) -> Command {
+    // NOTICE never gets an error reply
+    if params.len() < 2 {
+        return Command::Invalid("NOTICE".to_string(), None, "Not enough parameters".to_string());
+    }
+    let targets = params[0].split(",").map(String::from).collect();
+    Command::NOTICE { targets, text: params[1].to_owned() }
+}
 
 /* User Based Queries */
 #[allow(non_snake_case)]
@@ -323,10 +364,51 @@ fn parse_WHO(params: &[&str]) -> Command {
     // RFC has some weird syntax for masks. irc.libera.chat accepts anything.
     // let (_, mask) = all_consuming(mask).parse(params[0])?;
     let mask = params[0].to_owned();
-    Command::WHO { mask }
+    // "o" for operators only, then WHOX fields: "o%tnf,123"
+    let options = params.get(1).copied().unwrap_or("");
+    let (flags, whox) = match options.split_once('%') {
+        Some((flags, whox)) => (flags, Some(whox)),
+        None => (options, None),
+    };
+    let (fields, token) = match whox.map(|whox| whox.split_once(',').unwrap_or((whox, ""))) {
+        // the token (query type) is 1-3 digits, "0" if missing or malformed
+        Some((fields, token)) => {
+            let valid = (1..=3).contains(&token.len()) && token.bytes().all(|b| b.is_ascii_digit());
+            (Some(fields.to_string()), if valid { token.to_string() } else { "0".to_string() })
+        }
+        None => (None, String::new()),
+    };
+    Command::WHO { mask, opers_only: flags.contains('o'), fields, token }
+}
+#[allow(non_snake_case)]
+fn parse_WHOIS(params: &[&str]) -> Command {
+    let (target, nicks) = match params {
+        [] => {
+            return Command::Invalid(
+                "WHOIS".to_string(),
+                Some(ERR_NONICKNAMEGIVEN),
+                ":No nickname given".to_string(),
+            );
+        }
+        [nicks] => ("", nicks),
+        [target, nicks, ..] => (*target, nicks),
+    };
+    let nicks = nicks.split(",").filter(|nick| !nick.is_empty()).map(String::from).collect();
+    Command::WHOIS { target: target.to_string(), nicks }
+}
+#[allow(non_snake_case)]
+fn parse_WHOWAS(params: &[&str]) -> Command {
+    let Some(nick) = params.first() else {
+        return Command::Invalid(
+            "WHOWAS".to_string(),
+            Some(ERR_NONICKNAMEGIVEN),
+            ":No nickname given".to_string(),
+        );
+    };
+    // a count that is not positive means all entries; the server parameter is ignored
+    let count = params.get(1).and_then(|count| count.parse::<usize>().ok()).filter(|&c| c > 0);
+    Command::WHOWAS { nick: nick.to_string(), count }
 }
-// WHOIS
-// WHOWAS
 
 /* Operator Messages */
 // KILL
@@ -335,9 +417,37 @@ fn parse_WHO(params: &[&str]) -> Command {
 // SQUIT
 
 /* Optional Messages */
-// AWAY
+#[allow(non_snake_case)]
+fn parse_AWAY(params: &[&str]) -> Command {
+    Command::AWAY { text: params.first().unwrap_or(&"").to_string() }
+}
+#[allow(non_snake_case)]
+fn parse_ISON(params: &[&str]) -> Command {
+    // some clients send the nicks as one trailing parameter
+    let nicknames: Vec<String> =
+        params.iter().flat_map(|param| param.split_whitespace()).map(String::from).collect();
+    if nicknames.is_empty() {
+        return Command::Invalid(
+            "ISON".to_string(),
+            Some(ERR_NEEDMOREPARAMS),
+            "ISON :Not enough parameters".to_string(),
+        );
+    }
+    Command::ISON { nicknames }
+}
 // LINKS
-// USERHOST
+#[allow(non_snake_case)]
+fn parse_USERHOST(params: &[&str]) -> Command {
+    if params.is_empty() {
+        return Command::Invalid(
+            "USERHOST".to_string(),
+            Some(ERR_NEEDMOREPARAMS),
+            "USERHOST :Not enough parameters".to_string(),
+        );
+    }
+    let nicknames = params.iter().take(5).map(|s| s.to_string()).collect();
+    Command::USERHOST { nicknames }
+}
 // WALLOPS
 
 #[cfg(test)]
@@ -390,8 +500,36 @@ mod tests {
         );
     }
     // PING
-    // PONG
-    // OPER
+    #[test]
+    fn test_pong() {
+        assert_eq!(
+            parse_PONG(&[]),
+            Command::PONG { server: "".to_string(), token: "".to_string() }
+        );
+        assert_eq!(
+            parse_PONG(&["token"]),
+            Command::PONG { server: "".to_string(), token: "token".to_string() }
+        );
+        assert_eq!(
+            parse_PONG(&["server", "token"]),
+            Command::PONG { server: "server".to_string(), token: "token".to_string() }
+        );
+    }
+    #[test]
+    fn test_oper() {
+        assert_eq!(
+            parse_OPER(&["name"]),
+            Command::Invalid(
+                "OPER".to_string(),
+                Some(ERR_NEEDMOREPARAMS),
+                "OPER :Not enough parameters".to_string()
+            )
+        );
+        assert_eq!(
+            parse_OPER(&["name", "password"]),
+            Command::OPER { name: "name".to_string(), password: "password".to_string() }
+        );
+    }
     // QUIT
     // ERROR
 
@@ -443,7 +581,14 @@ mod tests {
             Command::TOPIC { channel: "#chan1".to_string(), topic: Some("topic".to_string()) }
         );
     }
-    // NAMES
+    #[test]
+    fn test_names() {
+        assert_eq!(parse_NAMES(&[]), Command::NAMES { channels: stringvec![] });
+        assert_eq!(
+            parse_NAMES(&["#chan1,#chan2"]),
+            Command::NAMES { channels: stringvec!["#chan1", "#chan2"] }
+        );
+    }
     #[test]
     fn test_list() {
         assert_eq!(parse_LIST(&[]), Command::LIST { channels: stringvec![], elistconds: None });
@@ -456,8 +601,44 @@ mod tests {
             Command::LIST { channels: stringvec!["#chan1", "#chan2", "#chan3"], elistconds: None }
         );
     }
-    // INVITE
-    // KICK
+    #[test]
+    fn test_invite() {
+        assert_eq!(
+            parse_INVITE(&[]),
+            Command::INVITE { nickname: "".to_string(), channel: "".to_string() }
+        );
+        assert!(matches!(
+            parse_INVITE(&["nick1"]),
+            Command::Invalid(_, Some(ERR_NEEDMOREPARAMS), _)
+        ));
+        assert_eq!(
+            parse_INVITE(&["nick1", "#chan1"]),
+            Command::INVITE { nickname: "nick1".to_string(), channel: "#chan1".to_string() }
+        );
+    }
+    #[test]
+    fn test_kick() {
+        assert!(matches!(
+            parse_KICK(&["#chan1"]),
+            Command::Invalid(_, Some(ERR_NEEDMOREPARAMS), _)
+        ));
+        assert_eq!(
+            parse_KICK(&["#chan1", "nick1"]),
+            Command::KICK {
+                channel: "#chan1".to_string(),
+                users: stringvec!["nick1"],
+                reason: "".to_string()
+            }
+        );
+        assert_eq!(
+            parse_KICK(&["#chan1", "nick1,nick2", "bye"]),
+            Command::KICK {
+                channel: "#chan1".to_string(),
+                users: stringvec!["nick1", "nick2"],
+                reason: "bye".to_string()
+            }
+        );
+    }
 
     /* Server Queries and Commands */
     // MOTD
@@ -475,17 +656,13 @@ mod tests {
             parse_MODE(&["t", "ab", "p1", "p2", "p3"]),
             Command::MODE {
                 target: "t".to_string(),
-                modestring: "+ab".to_string(),
-                modeargs: stringvec!["p1", "p2"],
+                modestring: "ab".to_string(),
+                modeargs: stringvec!["p1", "p2", "p3"],
             }
         );
         assert_eq!(
-            parse_MODE(&["t", "-m-+-m-+p--mm+p", "p1", "p2"]),
-            Command::MODE {
-                target: "t".to_string(),
-                modestring: "-mm+p-mm+p".to_string(),
-                modeargs: stringvec!["p1", "p2"],
-            }
+            parse_MODE(&["t"]),
+            Command::MODE { target: "t".to_string(), modestring: String::new(), modeargs: vec![] }
         );
     }
 
@@ -504,15 +681,84 @@ mod tests {
             }
         );
     }
-    // NOTICE
+    #[test]
+    fn test_notice() {
+        assert_eq!(
+            parse_NOTICE(&["#chan1,user1", "text"]),
+            Command::NOTICE { targets: stringvec!["#chan1", "user1"], text: "text".to_string() }
+        );
+        assert!(matches!(parse_NOTICE(&["#chan1"]), Command::Invalid(_, None, _)));
+    }
 
     /* User Based Queries */
     #[test]
     fn test_who() {
-        assert_eq!(parse_WHO(&["#chan1"]), Command::WHO { mask: "#chan1".to_string() });
+        assert_eq!(
+            parse_WHO(&["#chan1"]),
+            Command::WHO {
+                mask: "#chan1".to_string(),
+                opers_only: false,
+                fields: None,
+                token: String::new()
+            }
+        );
+        assert_eq!(
+            parse_WHO(&["*.example.com", "o"]),
+            Command::WHO {
+                mask: "*.example.com".to_string(),
+                opers_only: true,
+                fields: None,
+                token: String::new()
+            }
+        );
+        assert_eq!(
+            parse_WHO(&["#chan1", "%tna,42"]),
+            Command::WHO {
+                mask: "#chan1".to_string(),
+                opers_only: false,
+                fields: Some("tna".to_string()),
+                token: "42".to_string()
+            }
+        );
+        for options in ["%tna", "%tna,", "%tna,1234", "%tna,x"] {
+            assert_eq!(
+                parse_WHO(&["#chan1", options]),
+                Command::WHO {
+                    mask: "#chan1".to_string(),
+                    opers_only: false,
+                    fields: Some("tna".to_string()),
+                    token: "0".to_string()
+                }
+            );
+        }
+    }
+    #[test]
+    fn test_whois() {
+        assert_eq!(
+            parse_WHOIS(&["aki,bob"]),
+            Command::WHOIS { target: String::new(), nicks: stringvec!["aki", "bob"] }
+        );
+        assert_eq!(
+            parse_WHOIS(&["irc.example.com", "aki"]),
+            Command::WHOIS { target: "irc.example.com".to_string(), nicks: stringvec!["aki"] }
+        );
+        assert!(matches!(parse_WHOIS(&[]), Command::Invalid(_, Some(ERR_NONICKNAMEGIVEN), _)));
+    }
+    #[test]
+    fn test_whowas() {
+        assert_eq!(
+            parse_WHOWAS(&["aki"]),
+            Command::WHOWAS { nick: "aki".to_string(), count: None }
+        );
+        assert_eq!(
+            parse_WHOWAS(&["aki", "2", "irc.example.com"]),
+            Command::WHOWAS { nick: "aki".to_string(), count: Some(2) }
+        );
+        assert_eq!(
+            parse_WHOWAS(&["aki", "-1"]),
+            Command::WHOWAS { nick: "aki".to_string(), count: None }
+        );
     }
-    // WHOIS
-    // WHOWAS
 
     /* Operator Messages */
     // KILL
@@ -521,8 +767,27 @@ mod tests {
     // SQUIT
 
     /* Optional Messages */
-    // AWAY
+    #[test]
+    fn test_away() {
+        assert_eq!(parse_AWAY(&["gone"]), Command::AWAY { text: "gone".to_string() });
+        assert_eq!(parse_AWAY(&[]), Command::AWAY { text: String::new() });
+    }
+    #[test]
+    fn test_ison() {
+        assert_eq!(
+            parse_ISON(&["aki", "bob carl"]),
+            Command::ISON { nicknames: stringvec!["aki", "bob", "carl"] }
+        );
+        assert!(matches!(parse_ISON(&[""]), Command::Invalid(_, Some(ERR_NEEDMOREPARAMS), _)));
+    }
     // LINKS
-    // USERHOST
+    #[test]
+    fn test_userhost() {
+        assert_eq!(
+            parse_USERHOST(&["a", "b", "c", "d", "e", "f"]),
+            Command::USERHOST { nicknames: stringvec!["a", "b", "c", "d", "e"] }
+        );
+        assert!(matches!(parse_USERHOST(&[]), Command::Invalid(_, Some(ERR_NEEDMOREPARAMS), _)));
+    }
     // WALLOPS
 }
@@ -0,0 +1,157 @@
+// Matching of channel list modes (+b, +q, +e, +I), including extended bans.
+// An extban is `$` followed by an optional `~` to negate it, a type, and usually `:<argument>`:
+//   $a            users logged in to an account
+//   $a:<mask>     users logged in to an account matching the mask
+//   $r:<mask>     users whose realname matches the mask
+//   $j:<channel>  users banned from another channel
+// Anything else is a nick!user@host mask.
+
+use crate::{
+    channel::{Channel, ListEntry},
+    mask::wildcard_match,
+    server_state::ServerState,
+    user::User,
+};
+
+/// The extban types, as advertised in EXTBAN.
+pub const EXTBAN_TYPES: &str = "ajr";
+
+/// What a mask is matched against.
+pub struct BanTarget<'a> {
+    pub fqn: &'a str,
+    pub realname: &'a str,
+    pub account: Option<&'a str>,
+}
+
+/// `banned_in` answers `$j` extbans: whether the target is banned from the named channel.
+pub fn mask_matches(mask: &str, target: &BanTarget, banned_in: &dyn Fn(&str) -> bool) -> bool {
+    let Some(extban) = mask.strip_prefix('$') else {
+        return wildcard_match(mask, target.fqn);
+    };
+    let (negated, extban) = match extban.strip_prefix('~') {
+        Some(extban) => (true, extban),
+        None => (false, extban),
+    };
+    let matched = match extban.split_once(':') {
+        None if extban == "a" => target.account.is_some(),
+        Some(("a", mask)) => target.account.is_some_and(|account| wildcard_match(mask, account)),
+        Some(("r", mask)) => wildcard_match(mask, target.realname),
+        Some(("j", channel)) => banned_in(channel),
+        // rejected when set
+        _ => return false,
+    };
+    matched != negated
+}
+
+/// Whether an extban has a known type and the argument that type needs.
+pub fn is_valid_extban(mask: &str) -> bool {
+    let Some(extban) = mask.strip_prefix('$') else {
+        return false;
+    };
+    let extban = extban.strip_prefix('~').unwrap_or(extban);
+    match extban.split_once(':') {
+        None => extban == "a",
+        Some(("a" | "r", mask)) => !mask.is_empty(),
+        Some(("j", channel)) => channel.starts_with(['#', '&']),
+        _ => false,
+    }
+}
+
+/// Whether the user matches the channel's `list` (`b` or `q`) and no ban exception.
+pub fn is_banned(server: &ServerState, channel: &Channel, user: &User, list: char) -> bool {
+    let (fqn, account) = (user.get_fqn_string(), user.account());
+    let target = BanTarget { fqn: &fqn, realname: &user.realname, account: account.as_deref() };
+    let banned_in = banned_in(server, &target);
+    matches_any(&channel.get_list(list), &target, &banned_in)
+        && !matches_any(&channel.get_list('e'), &target, &banned_in)
+}
+
+/// Whether the user may join while the channel is invite-only without being invited.
+pub fn is_invite_exempt(server: &ServerState, channel: &Channel, user: &User) -> bool {
+    let (fqn, account) = (user.get_fqn_string(), user.account());
+    let target = BanTarget { fqn: &fqn, realname: &user.realname, account: account.as_deref() };
+    matches_any(&channel.get_list('I'), &target, &banned_in(server, &target))
+}
+
+/// Answers `$j`. Only the other channel's hostmask bans and exceptions count,
+/// so that channels cannot refer to each other in a loop.
+fn banned_in<'a>(server: &'a ServerState, target: &'a BanTarget) -> impl Fn(&str) -> bool + 'a {
+    move |name| {
+        server.get_channel(name).is_some_and(|other| {
+            matches_any(&other.get_list('b'), target, &never)
+                && !matches_any(&other.get_list('e'), target, &never)
+        })
+    }
+}
+
+fn matches_any(list: &[ListEntry], target: &BanTarget, banned_in: &dyn Fn(&str) -> bool) -> bool {
+    list.iter().any(|entry| mask_matches(&entry.mask, target, banned_in))
+}
+
+fn never(_: &str) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUEST: BanTarget =
+        BanTarget { fqn: "guest!~g@host.example.com", realname: "Some Guest", account: None };
+    const AKI: BanTarget =
+        BanTarget { fqn: "aki!~aki@example.net", realname: "aki", account: Some("Aki") };
+
+    #[test]
+    fn test_hostmask() {
+        assert!(mask_matches("*!*@*.example.com", &GUEST, &never));
+        assert!(!mask_matches("*!*@*.example.com", &AKI, &never));
+    }
+
+    #[test]
+    fn test_account() {
+        assert!(mask_matches("$a", &AKI, &never));
+        assert!(!mask_matches("$a", &GUEST, &never));
+        assert!(mask_matches("$a:aki", &AKI, &never));
+        assert!(!mask_matches("$a:bob", &AKI, &never));
+        assert!(mask_matches("$~a", &GUEST, &never));
+        assert!(!mask_matches("$~a", &AKI, &never));
+    }
+
+    #[test]
+    fn test_realname() {
+        assert!(mask_matches("$r:*guest", &GUEST, &never));
+        assert!(!mask_matches("$r:*guest", &AKI, &never));
+        assert!(mask_matches("$~r:*guest", &AKI, &never));
+    }
+
+    #[tokio::test]
+    async fn test_connected_user() {
+        // without accounts, a connected user only ever matches $~a
+        let (user, _) = User::for_test("guest");
+        let (fqn, account) = (user.get_fqn_string(), user.account());
+        let target = BanTarget { fqn: &fqn, realname: &user.realname, account: account.as_deref() };
+        assert!(!mask_matches("$a", &target, &never));
+        assert!(!mask_matches("$a:*", &target, &never));
+        assert!(mask_matches("$~a", &target, &never));
+        assert!(mask_matches("$r:*", &target, &never));
+        assert!(!mask_matches("$~r:*", &target, &never));
+    }
+
+    #[test]
+    fn test_channel() {
+        let banned_in = |channel: &str| channel == "#spam";
+        assert!(mask_matches("$j:#spam", &GUEST, &banned_in));
+        assert!(!mask_matches("$j:#other", &GUEST, &banned_in));
+    }
+
+    #[test]
+    fn test_is_valid_extban() {
+        for valid in ["$a", "$~a", "$a:aki", "$r:*bot*", "$~r:x", "$j:#chan"] {
+            assert!(is_valid_extban(valid), "{valid}");
+        }
+        for invalid in ["$", "$x", "$a:", "$r", "$j:chan", "nick!*@*"] {
+            assert!(!is_valid_extban(invalid), "{invalid}");
+        }
+        assert!(!mask_matches("$x:anything", &GUEST, &never));
+    }
+}
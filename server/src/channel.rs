@@ -1,5 +1,5 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fmt::{Debug, Display},
     hash::Hash,
     sync::{Arc, Mutex, Weak},
@@ -13,14 +13,66 @@ use crate::user::{SharedUser, WeakUser};
 /// Each mode is one of four types, as specified by IRCv3 docs.  
 /// ChannelModes only stores modes, and Channel provides no checks for privaleges.  
 /// I.e. Channel and ChannelModes only sees arbitrary letters. Implementation must be elsewhere.
+#[allow(non_snake_case)]
 #[derive(Clone)]
 pub struct ChannelModes {
     /* Type A: list modes */
-    /* Type B: param on set */
-    /* Type C: param always */
+    /// bans
+    pub b: Vec<ListEntry>,
+    /// exceptions to bans
+    pub e: Vec<ListEntry>,
+    /// invite exceptions
+    pub I: Vec<ListEntry>,
+    /// quiets: like bans, but only for speaking
+    pub q: Vec<ListEntry>,
+    /* Type B: param always */
+    /// channel key
+    pub k: Option<String>,
+    /* Type C: param on set */
+    /// user limit
+    pub l: Option<usize>,
     /* Type D: no params */
+    /// invite-only channel
+    pub i: bool,
+    /// moderated: only voiced members and ops may speak
+    pub m: bool,
+    /// no messages from outside the channel
+    pub n: bool,
+    /// private channel
+    pub p: bool,
     /// secret channel
     pub s: bool,
+    /// only ops may set the topic
+    pub t: bool,
+}
+
+/// A mask in one of the list modes.
+#[derive(Clone)]
+pub struct ListEntry {
+    pub mask: String,
+    /// nick!user@host of whoever set it
+    pub setter: String,
+    pub time: u64,
+}
+
+/// Status of a user in a channel. Prefix modes (`+o`, `+v`) are kept here rather than in ChannelModes.
+#[derive(Clone, Copy, Default)]
+pub struct Membership {
+    pub op: bool,
+    pub voice: bool,
+}
+
+impl Membership {
+    /// The prefix of the highest status, shown before the nick in NAMES and WHO.
+    pub fn prefix(&self) -> &'static str {
+        if self.op {
+            "@"
+        } else if self.voice {
+            "+"
+        } else {
+            ""
+        }
+    }
 }
 
 pub struct Channel {
@@ -28,7 +80,9 @@ pub struct Channel {
     pub name: String,
     /// topic, who, time
     topic_info: Mutex<Option<(String, String, String)>>,
-    users: Mutex<HashSet<WeakUser>>,
+    users: Mutex<HashMap<WeakUser, Membership>>,
+    /// Users invited with INVITE who have not joined yet.
+    invites: Mutex<HashSet<WeakUser>>,
     modes: Mutex<ChannelModes>,
 }
 #[derive(Clone)]
@@ -44,9 +98,23 @@ impl Channel {
                 .as_secs()
                 .to_string(),
             name,
-            users: Mutex::new(HashSet::new()),
+            users: Mutex::new(HashMap::new()),
             topic_info: Mutex::new(None),
-            modes: Mutex::new(ChannelModes { s: false }),
+            invites: Mutex::new(HashSet::new()),
+            modes: Mutex::new(ChannelModes {
+                b: Vec::new(),
+                e: Vec::new(),
+                I: Vec::new(),
+                q: Vec::new(),
+                k: None,
+                l: None,
+                i: false,
+                m: false,
+                n: false,
+                p: false,
+                s: false,
+                t: false,
+            }),
         }
     }
 
@@ -64,24 +132,70 @@ impl Channel {
 
     /* Users */
     pub fn contains_user(&self, user: &SharedUser) -> bool {
-        self.users.lock().unwrap().contains(&WeakUser(Arc::downgrade(user)))
+        self.users.lock().unwrap().contains_key(&WeakUser(Arc::downgrade(user)))
     }
     pub fn user_count(&self) -> usize {
         self.users.lock().unwrap().len()
     }
     /// Snapshot of users in this channel.
     pub fn get_users(&self) -> impl Iterator<Item = SharedUser> {
-        self.users.lock().unwrap().clone().into_iter().map(|user| user.0.upgrade().unwrap())
+        self.get_members().into_iter().map(|(user, _)| user)
+    }
+    /// Snapshot of users in this channel with their status.
+    pub fn get_members(&self) -> Vec<(SharedUser, Membership)> {
+        let users = self.users.lock().unwrap();
+        users.iter().map(|(user, membership)| (user.0.upgrade().unwrap(), *membership)).collect()
+    }
+    pub fn get_membership(&self, user: &SharedUser) -> Option<Membership> {
+        self.users.lock().unwrap().get(&WeakUser(Arc::downgrade(user))).copied()
+    }
+    pub fn is_op(&self, user: &SharedUser) -> bool {
+        self.get_membership(user).is_some_and(|membership| membership.op)
+    }
+    /// Sets `o` or `v` for a member. Returns false if they are not a member or already had it.
+    pub fn set_member_mode(&self, user: &SharedUser, mode: char, value: bool) -> bool {
+        let mut users = self.users.lock().unwrap();
+        let Some(membership) = users.get_mut(&WeakUser(Arc::downgrade(user))) else {
+            return false;
+        };
+        let flag = match mode {
+            'o' => &mut membership.op,
+            'v' => &mut membership.voice,
+            _ => panic!("mode was not checked first!"),
+        };
+        if *flag == value {
+            false
+        } else {
+            *flag = value;
+            true
+        }
     }
     /// Snapshot of nicks in this channel.
     pub fn get_nicks(&self) -> impl Iterator<Item = String> {
         self.get_users().map(|user| user.get_nickname())
     }
     pub fn _add_user(&self, user: &SharedUser) -> bool {
-        self.users.lock().unwrap().insert(WeakUser(Arc::downgrade(user)))
+        let mut users = self.users.lock().unwrap();
+        let weak = WeakUser(Arc::downgrade(user));
+        if users.contains_key(&weak) {
+            return false;
+        }
+        users.insert(weak, Membership::default());
+        true
     }
     pub fn _remove_user(&self, user: &SharedUser) -> bool {
-        self.users.lock().unwrap().remove(&WeakUser(Arc::downgrade(user)))
+        self.users.lock().unwrap().remove(&WeakUser(Arc::downgrade(user))).is_some()
+    }
+
+    /* Invites */
+    pub fn invite(&self, user: &SharedUser) {
+        self.invites.lock().unwrap().insert(WeakUser(Arc::downgrade(user)));
+    }
+    pub fn is_invited(&self, user: &SharedUser) -> bool {
+        self.invites.lock().unwrap().contains(&WeakUser(Arc::downgrade(user)))
+    }
+    pub fn remove_invite(&self, user: &SharedUser) {
+        self.invites.lock().unwrap().remove(&WeakUser(Arc::downgrade(user)));
     }
 
     /* Modes */
@@ -89,10 +203,60 @@ impl Channel {
     pub fn get_modes(&self) -> ChannelModes {
         self.modes.lock().unwrap().clone()
     }
+    /// Snapshot of the `b`, `e`, `I` or `q` list.
+    pub fn get_list(&self, mode: char) -> Vec<ListEntry> {
+        list_mut(&mut self.modes.lock().unwrap(), mode).clone()
+    }
+    /// Number of entries in all list modes.
+    pub fn list_count(&self) -> usize {
+        let modes = self.modes.lock().unwrap();
+        modes.b.len() + modes.e.len() + modes.I.len() + modes.q.len()
+    }
+    /// Returns false if the list already has the mask.
+    pub fn add_list_entry(&self, mode: char, mask: &str, setter: &str) -> bool {
+        let mut modes = self.modes.lock().unwrap();
+        let list = list_mut(&mut modes, mode);
+        if list.iter().any(|entry| entry.mask.eq_ignore_ascii_case(mask)) {
+            return false;
+        }
+        let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
+        list.push(ListEntry { mask: mask.to_string(), setter: setter.to_string(), time });
+        true
+    }
+    /// Returns the removed mask as it was set, if the list had it.
+    pub fn remove_list_entry(&self, mode: char, mask: &str) -> Option<String> {
+        let mut modes = self.modes.lock().unwrap();
+        let list = list_mut(&mut modes, mode);
+        let index = list.iter().position(|entry| entry.mask.eq_ignore_ascii_case(mask))?;
+        Some(list.remove(index).mask)
+    }
+    pub fn set_key(&self, key: Option<String>) -> bool {
+        let mut modes = self.modes.lock().unwrap();
+        if modes.k == key {
+            false
+        } else {
+            modes.k = key;
+            true
+        }
+    }
+    pub fn set_limit(&self, limit: Option<usize>) -> bool {
+        let mut modes = self.modes.lock().unwrap();
+        if modes.l == limit {
+            false
+        } else {
+            modes.l = limit;
+            true
+        }
+    }
     pub fn set_mode_type_d(&self, mode: char, value: bool) -> bool {
         let mut modes = self.modes.lock().unwrap();
         let flag = match mode {
+            'i' => &mut modes.i,
+            'm' => &mut modes.m,
+            'n' => &mut modes.n,
+            'p' => &mut modes.p,
             's' => &mut modes.s,
+            't' => &mut modes.t,
             _ => panic!("mode was not checked first!"),
         };
         if *flag == value {
@@ -111,6 +275,16 @@ impl Channel {
     }
 }
 
+fn list_mut(modes: &mut ChannelModes, mode: char) -> &mut Vec<ListEntry> {
+    match mode {
+        'b' => &mut modes.b,
+        'e' => &mut modes.e,
+        'I' => &mut modes.I,
+        'q' => &mut modes.q,
+        _ => panic!("mode was not checked first!"),
+    }
+}
+
 impl Display for Channel {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "({} | {})", self.name, self.get_nicks().collect::<Vec<_>>().join(", "))
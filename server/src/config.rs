@@ -0,0 +1,458 @@
+use std::{
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Deserializer, de::Error as _};
+use thiserror::Error;
+
+use crate::{
+    CHANNEL_FLAGS, CHANNEL_MODE_CLASSES, CHANTYPES, STATUSMSG, ban::EXTBAN_TYPES,
+    mask::wildcard_match,
+};
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("No config file was given at startup")]
+    NoPath,
+    #[error("Could not read {0}: {1}")]
+    Io(PathBuf, io::Error),
+    /// Path, line number, message
+    #[error("Could not parse {0}, line {1}: {2}")]
+    Toml(PathBuf, usize, String),
+    #[error("Could not load TLS certificate/key {0}: {1}")]
+    Tls(PathBuf, String),
+    #[error("Invalid setting in {0}: {1}")]
+    Invalid(PathBuf, String),
+}
+
+/// Server settings, loaded from a TOML file given on the command line.
+/// Any field missing from the file takes its value from `Config::default()`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub servername: String,
+    pub network: String,
+    #[serde(rename = "listener")]
+    pub listeners: Vec<ListenerConfig>,
+    /// Certificate for listeners with `tls` enabled.
+    pub tls: Option<TlsConfig>,
+    /// Used if `motd_file` is not set.
+    pub motd: String,
+    /// Relative paths are resolved from the directory of the config file.
+    pub motd_file: Option<PathBuf>,
+    /// Flag modes set on new channels, e.g. "nt".
+    #[serde(deserialize_with = "channel_flags")]
+    pub channel_modes: String,
+    /// Departed and changed nicks remembered for WHOWAS.
+    pub whowas_size: usize,
+    pub limits: Limits,
+    pub timeouts: Timeouts,
+    /// Clients are put in the first class matching their host.
+    #[serde(rename = "class")]
+    pub classes: Vec<ConnectionClass>,
+    #[serde(rename = "oper")]
+    pub opers: Vec<Oper>,
+    /// Server bans, checked when a client registers.
+    #[serde(rename = "ban")]
+    pub bans: Vec<Ban>,
+}
+
+/// In seconds. Changes apply to new connections.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    /// Time allowed to complete NICK/USER registration.
+    pub registration: u64,
+    /// Idle time before the server sends a PING.
+    pub ping_interval: u64,
+    /// Time allowed to answer the PING, or send any other line, before being disconnected.
+    pub ping_timeout: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    pub addr: SocketAddr,
+    #[serde(default)]
+    pub tls: bool,
+    /// Only clients whose user@host matches the host of an oper block may register.
+    #[serde(default)]
+    pub oper_only: bool,
+    /// Connections start with a PROXY protocol header giving the real client address.
+    #[serde(default)]
+    pub proxy: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM certificate chain. Relative paths are resolved from the directory of the config file.
+    pub cert: PathBuf,
+    /// PEM private key. Relative paths are resolved from the directory of the config file.
+    pub key: PathBuf,
+}
+
+/// Limits advertised through ISUPPORT.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub nicklen: usize,
+    pub topiclen: usize,
+    pub userlen: usize,
+    pub kicklen: usize,
+    /// Entries allowed in a channel's ban, exception, invite exception and quiet lists combined.
+    pub maxlist: usize,
+    /// Mode changes with a parameter applied from one MODE command.
+    pub modes: usize,
+    /// Targets of one PRIVMSG or NOTICE.
+    pub maxtargets: usize,
+    pub awaylen: usize,
+}
+
+/// Per-connection settings for a group of clients.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionClass {
+    pub name: String,
+    /// Masks matched against the client's hostname and IP address.
+    pub hosts: Vec<String>,
+    /// Bytes that may wait to be sent to a client before it is disconnected.
+    pub sendq: usize,
+    /// Commands that may be sent at once before they start being delayed.
+    pub flood_burst: u32,
+    /// Commands per second allowed after the burst.
+    pub flood_rate: f64,
+    /// Seconds of delay a client may build up before it is disconnected for flooding.
+    pub flood_max_delay: u64,
+    /// Disables flood control, e.g. for trusted bots. Opers are always exempt.
+    pub flood_exempt: bool,
+}
+
+/// Credentials for the OPER command.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Oper {
+    pub name: String,
+    pub password: String,
+    /// user@host mask the oper must connect from. Without one, OPER works from any host,
+    /// but the block does not let anyone register on an oper-only listener.
+    #[serde(default)]
+    pub host: Option<String>,
+}
+
+impl Oper {
+    /// Whether OPER may be used from `userhost`.
+    pub fn host_matches(&self, userhost: &str) -> bool {
+        self.host.as_deref().is_none_or(|mask| wildcard_match(mask, userhost))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Ban {
+    /// nick!user@host mask
+    pub mask: String,
+    #[serde(default)]
+    pub reason: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            servername: String::from("akiRC.chat"),
+            network: String::from("akiRC"),
+            listeners: vec![ListenerConfig {
+                addr: SocketAddr::from(([0, 0, 0, 0], 6667)),
+                tls: false,
+                oper_only: false,
+                proxy: false,
+            }],
+            tls: None,
+            motd: String::from("<3"),
+            motd_file: None,
+            channel_modes: String::from("nt"),
+            whowas_size: 1000,
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+            classes: vec![ConnectionClass::default()],
+            opers: Vec::new(),
+            bans: Vec::new(),
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            nicklen: 16,
+            topiclen: 307,
+            userlen: 10,
+            kicklen: 307,
+            maxlist: 100,
+            modes: 4,
+            maxtargets: 4,
+            awaylen: 200,
+        }
+    }
+}
+
+impl Default for ConnectionClass {
+    fn default() -> Self {
+        ConnectionClass {
+            name: String::from("default"),
+            hosts: vec![String::from("*")],
+            sendq: 1 << 20,
+            flood_burst: 10,
+            flood_rate: 1.0,
+            flood_max_delay: 10,
+            flood_exempt: false,
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts { registration: 30, ping_interval: 120, ping_timeout: 60 }
+    }
+}
+
+impl Config {
+    /// Reads and parses the config file, then reads the MOTD file if one is given.
+    /// TLS certificates are not read until the listeners are started.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+        let mut config: Config = toml::from_str(&text).map_err(|e| {
+            let line = e.span().map_or(0, |span| text[..span.start].matches('\n').count() + 1);
+            ConfigError::Toml(path.to_owned(), line, e.message().to_owned())
+        })?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        if let Some(tls) = &mut config.tls {
+            tls.cert = dir.join(&tls.cert);
+            tls.key = dir.join(&tls.key);
+        }
+        if let Some(motd_file) = &config.motd_file {
+            let motd_path = dir.join(motd_file);
+            config.motd =
+                fs::read_to_string(&motd_path).map_err(|e| ConfigError::Io(motd_path, e))?;
+        }
+        config.validate().map_err(|e| ConfigError::Invalid(path.to_owned(), e))?;
+        Ok(config)
+    }
+
+    /// Checks values that parse but would break the server.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.listeners.is_empty() {
+            return Err("at least one [[listener]] is required".to_string());
+        }
+        let limits = &self.limits;
+        for (name, value) in [
+            ("nicklen", limits.nicklen),
+            ("userlen", limits.userlen),
+            ("modes", limits.modes),
+            ("maxtargets", limits.maxtargets),
+        ] {
+            if value == 0 {
+                return Err(format!("limits.{name} must be at least 1"));
+            }
+        }
+        for class in &self.classes {
+            if !(class.flood_rate.is_finite() && class.flood_rate > 0.0) {
+                return Err(format!("class {}: flood_rate must be above 0", class.name));
+            }
+            if class.flood_burst == 0 {
+                return Err(format!("class {}: flood_burst must be at least 1", class.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `userhost` may register on an oper-only listener.
+    /// Only oper blocks with a configured host count.
+    pub fn oper_host_allowed(&self, userhost: &str) -> bool {
+        self.opers
+            .iter()
+            .any(|oper| oper.host.as_deref().is_some_and(|mask| wildcard_match(mask, userhost)))
+    }
+
+    /// The first class matching the hostname or IP, or the default class if none do.
+    pub fn class_for(&self, hostname: &str, ip: &str) -> ConnectionClass {
+        self.classes
+            .iter()
+            .find(|class| {
+                class
+                    .hosts
+                    .iter()
+                    .any(|mask| wildcard_match(mask, hostname) || wildcard_match(mask, ip))
+            })
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn isupport_tokens(&self) -> Vec<String> {
+        vec![
+            format!("AWAYLEN={}", self.limits.awaylen),
+            // String::from("CASEMAPPING=ascii"),
+            // String::from("CHANLIMIT=#:25"),
+            format!(
+                "CHANMODES={},{},{},{}",
+                CHANNEL_MODE_CLASSES.a,
+                CHANNEL_MODE_CLASSES.b,
+                CHANNEL_MODE_CLASSES.c,
+                CHANNEL_MODE_CLASSES.d
+            ),
+            // String::from("CHANNELLEN=32"),
+            format!("CHANTYPES={}", CHANTYPES),
+            // String::from("ELIST..."),
+            String::from("EXCEPTS"),
+            format!("EXTBAN=$,{}", EXTBAN_TYPES),
+            // String::from("HOSTLEN=64"),
+            String::from("INVEX"),
+            format!("KICKLEN={}", self.limits.kicklen),
+            format!("MAXLIST={}:{}", CHANNEL_MODE_CLASSES.a, self.limits.maxlist),
+            // String::from("MAXTARGET"),
+            format!("MODES={}", self.limits.modes),
+            format!("NETWORK={}", self.network),
+            format!("NICKLEN={}", self.limits.nicklen),
+            String::from("PREFIX=(ov)@+"),
+            // String::from("SAFELIST"),
+            // String::from("SILENCE"),
+            format!("STATUSMSG={}", STATUSMSG),
+            format!("TARGMAX=PRIVMSG:{0},NOTICE:{0}", self.limits.maxtargets),
+            format!("TOPICLEN={}", self.limits.topiclen),
+            format!("USERLEN={}", self.limits.userlen),
+            String::from("WHOX"),
+        ]
+    }
+}
+
+/// Accepts flag modes only, with an optional leading '+'.
+fn channel_flags<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let modes = String::deserialize(deserializer)?;
+    let modes = modes.strip_prefix('+').unwrap_or(&modes);
+    match modes.chars().find(|&c| !CHANNEL_FLAGS.contains(c)) {
+        Some(c) => Err(D::Error::custom(format!("'{c}' is not a channel flag mode"))),
+        None => Ok(modes.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_config() {
+        let config: Config = toml::from_str(
+            r#"
+            servername = "irc.example.com"
+            [limits]
+            nicklen = 30
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.servername, "irc.example.com");
+        assert_eq!(config.network, "akiRC");
+        assert_eq!(config.limits.nicklen, 30);
+        assert_eq!(config.limits.topiclen, 307);
+        assert!(config.isupport_tokens().contains(&String::from("NICKLEN=30")));
+    }
+
+    #[test]
+    fn test_opers_and_bans() {
+        let config: Config = toml::from_str(
+            r#"
+            [[oper]]
+            name = "aki"
+            password = "hunter2"
+            [[ban]]
+            mask = "*!*@*.example.com"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.opers[0].name, "aki");
+        assert_eq!(config.opers[0].host, None);
+        assert!(config.opers[0].host_matches("~u@anywhere"));
+        assert_eq!(config.bans[0].mask, "*!*@*.example.com");
+        assert_eq!(config.bans[0].reason, "");
+    }
+
+    #[test]
+    fn test_listeners() {
+        let config: Config = toml::from_str(
+            r#"
+            [[listener]]
+            addr = "[::]:6667"
+            [[listener]]
+            addr = "127.0.0.1:6697"
+            tls = true
+            proxy = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.listeners.len(), 2);
+        assert!(config.listeners[0].addr.is_ipv6());
+        assert!(config.listeners[1].tls && config.listeners[1].proxy);
+        assert!(!config.listeners[1].oper_only);
+        assert_eq!(Config::default().listeners[0].addr.port(), 6667);
+    }
+
+    #[test]
+    fn test_class_for() {
+        let config: Config = toml::from_str(
+            r#"
+            [[class]]
+            name = "bots"
+            hosts = ["127.0.0.1", "*.bots.example.com"]
+            sendq = 5000000
+            [[class]]
+            name = "users"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.class_for("localhost", "127.0.0.1").name, "bots");
+        assert_eq!(config.class_for("a.bots.example.com", "10.0.0.1").name, "bots");
+        assert_eq!(config.class_for("example.com", "10.0.0.1").name, "users");
+        assert_eq!(config.class_for("example.com", "10.0.0.1").sendq, 1 << 20);
+        assert_eq!(Config { classes: vec![], ..config }.class_for("", "").name, "default");
+    }
+
+    #[test]
+    fn test_example_config() {
+        toml::from_str::<Config>(include_str!("../config.toml")).unwrap();
+    }
+
+    #[test]
+    fn test_channel_modes() {
+        let config: Config = toml::from_str("channel_modes = \"+ns\"").unwrap();
+        assert_eq!(config.channel_modes, "ns");
+        assert!(toml::from_str::<Config>("channel_modes = \"nk\"").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let config: Config = toml::from_str("[[class]]\nflood_rate = 0.0").unwrap();
+        assert!(config.validate().is_err());
+        let config: Config = toml::from_str("[[class]]\nflood_burst = 0").unwrap();
+        assert!(config.validate().is_err());
+        let config: Config = toml::from_str("[limits]\nuserlen = 0").unwrap();
+        assert!(config.validate().is_err());
+        let config: Config = toml::from_str("listener = []").unwrap();
+        assert!(config.validate().is_err());
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_error_line() {
+        let path = std::env::temp_dir().join(format!("akirc-test-{}.toml", std::process::id()));
+        fs::write(&path, "servername = \"x\"\n[limits]\nnicklen = \"big\"\n").unwrap();
+        let result = Config::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(ConfigError::Toml(_, 3, _))));
+    }
+
+    #[test]
+    fn test_unknown_field() {
+        assert!(toml::from_str::<Config>("servrename = \"typo\"").is_err());
+    }
+}
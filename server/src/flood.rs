@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::config::ConnectionClass;
+
+/// Token bucket limiting how fast one client's commands are processed ("fakelag").
+/// Commands past the burst are delayed, and the client is disconnected once the delay
+/// owed grows past the hard limit.
+pub struct FloodControl {
+    /// Goes negative while commands are being delayed.
+    tokens: f64,
+    burst: f64,
+    /// Tokens regained per second.
+    rate: f64,
+    max_delay: Duration,
+    last_update: Instant,
+}
+
+impl FloodControl {
+    pub fn new(class: &ConnectionClass, now: Instant) -> Self {
+        FloodControl {
+            tokens: class.flood_burst as f64,
+            burst: class.flood_burst as f64,
+            rate: class.flood_rate,
+            max_delay: Duration::from_secs(class.flood_max_delay),
+            last_update: now,
+        }
+    }
+
+    /// Takes a token for one command. Returns how long to wait before processing it,
+    /// or None if the client is over the hard limit.
+    pub fn take(&mut self, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.last_update).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst) - 1.0;
+        self.last_update = now;
+        if self.tokens >= 0.0 {
+            return Some(Duration::ZERO);
+        }
+        // a non-finite delay can only come from a bad rate, and would panic in Duration
+        let delay = Duration::try_from_secs_f64(-self.tokens / self.rate).ok()?;
+        (delay <= self.max_delay).then_some(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class() -> ConnectionClass {
+        ConnectionClass {
+            flood_burst: 3,
+            flood_rate: 1.0,
+            flood_max_delay: 2,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_burst_then_delay() {
+        let now = Instant::now();
+        let mut flood = FloodControl::new(&class(), now);
+        for _ in 0..3 {
+            assert_eq!(flood.take(now), Some(Duration::ZERO));
+        }
+        assert_eq!(flood.take(now), Some(Duration::from_secs(1)));
+        assert_eq!(flood.take(now), Some(Duration::from_secs(2)));
+        assert_eq!(flood.take(now), None);
+    }
+
+    #[test]
+    fn test_refill() {
+        let now = Instant::now();
+        let mut flood = FloodControl::new(&class(), now);
+        for _ in 0..4 {
+            flood.take(now);
+        }
+        // one token of debt is paid back after a second, and the bucket never exceeds the burst
+        assert_eq!(flood.take(now + Duration::from_secs(1)), Some(Duration::from_secs(1)));
+        let later = now + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(flood.take(later), Some(Duration::ZERO));
+        }
+        assert_eq!(flood.take(later), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_zero_rate() {
+        let now = Instant::now();
+        let mut flood = FloodControl::new(&ConnectionClass { flood_rate: 0.0, ..class() }, now);
+        for _ in 0..3 {
+            assert_eq!(flood.take(now), Some(Duration::ZERO));
+        }
+        assert_eq!(flood.take(now), None);
+    }
+}
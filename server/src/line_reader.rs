@@ -0,0 +1,108 @@
+use std::io;
+
+use common::{
+    IrcError,
+    message::{Command, MAX_LINE_LEN, Message, Numeric::ERR_INPUTTOOLONG},
+};
+use owo_colors::OwoColorize;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+
+/// Splits a client's input into messages, holding at most MAX_LINE_LEN bytes of a line.
+pub struct LineReader<R> {
+    reader: BufReader<R>,
+    /// The start of a line that has not been completely read yet.
+    buffer: Vec<u8>,
+    /// Set while skipping the rest of a line that was too long.
+    discarding: bool,
+}
+
+impl<R: AsyncRead + Unpin> LineReader<R> {
+    pub fn new(reader: R) -> Self {
+        LineReader { reader: BufReader::new(reader), buffer: Vec::new(), discarding: false }
+    }
+
+    /// Reads and parses the next non-empty line.
+    /// A line over the limit is dropped and returned as an invalid message replying ERR_INPUTTOOLONG.
+    /// Cancel safe: a partially read line is kept in the buffer.
+    pub async fn next_message(&mut self) -> Result<Message, IrcError> {
+        loop {
+            if let Some(pos_rn) = self.buffer.iter().position(|&b| b == b'\r' || b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..(pos_rn + 1)).collect();
+                if std::mem::take(&mut self.discarding) {
+                    continue;
+                }
+                if pos_rn > MAX_LINE_LEN - 2 {
+                    return Ok(input_too_long());
+                }
+                let msg_str = String::from_utf8_lossy(&line[..pos_rn]);
+                if !msg_str.is_empty() {
+                    let res: Result<Message, IrcError> = msg_str.parse();
+                    match &res {
+                        Ok(m) if matches!(m, Message { command: Command::Invalid(..), .. }) => {
+                            println!("{}", format!("<- [{msg_str}] -- {m}").bright_purple())
+                        }
+                        Ok(_) => {
+                            println!("{}", format!("<- {msg_str}").bright_blue())
+                        }
+                        Err(err) => {
+                            println!("{}", format!("<- [{msg_str}] -- {err}").bright_red())
+                        }
+                    }
+                    return res;
+                }
+            } else if self.buffer.len() >= MAX_LINE_LEN {
+                self.buffer.clear();
+                if !std::mem::replace(&mut self.discarding, true) {
+                    return Ok(input_too_long());
+                }
+            } else {
+                let limit = (MAX_LINE_LEN - self.buffer.len()) as u64;
+                if (&mut self.reader).take(limit).read_until(b'\n', &mut self.buffer).await? == 0 {
+                    return Err(IrcError::Io(io::Error::from(io::ErrorKind::UnexpectedEof)));
+                }
+            }
+        }
+    }
+}
+
+fn input_too_long() -> Message {
+    println!("{}", "<- [input line too long]".bright_purple());
+    Message::new(
+        None,
+        Command::Invalid(
+            String::new(),
+            Some(ERR_INPUTTOOLONG),
+            ":Input line was too long".to_string(),
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_too_long(msg: &Message) -> bool {
+        matches!(msg.command, Command::Invalid(_, Some(ERR_INPUTTOOLONG), _))
+    }
+
+    #[tokio::test]
+    async fn test_line_limit() {
+        let longest = format!("PING {}\r\n", "a".repeat(MAX_LINE_LEN - 7));
+        let too_long = format!("PING {}\r\n", "a".repeat(MAX_LINE_LEN - 6));
+        let very_long = format!("PING {}\r\n", "a".repeat(MAX_LINE_LEN * 3));
+        let input = format!("{longest}{too_long}{very_long}PING end\r\n");
+        let mut reader = LineReader::new(input.as_bytes());
+
+        assert!(matches!(
+            reader.next_message().await.unwrap().command,
+            Command::PING { token } if token.len() == MAX_LINE_LEN - 7
+        ));
+        assert!(is_too_long(&reader.next_message().await.unwrap()));
+        assert!(is_too_long(&reader.next_message().await.unwrap()));
+        assert!(matches!(
+            reader.next_message().await.unwrap().command,
+            Command::PING { token } if token == "end"
+        ));
+        assert!(matches!(reader.next_message().await, Err(IrcError::Io(_))));
+    }
+}
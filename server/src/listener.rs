@@ -0,0 +1,150 @@
+use std::{collections::HashMap, io, net::SocketAddr, time::Duration};
+
+use owo_colors::OwoColorize;
+use socket2::{Domain, Socket, Type};
+use tokio::{net::TcpListener, sync::watch, task, time::timeout};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{
+    config::Config, handle_connection, proxy, server_state::SharedServerState, tls::load_acceptor,
+};
+
+/// Time allowed for each of the PROXY header and the TLS handshake,
+/// before the registration timeout starts.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Options of a running listener, which can be changed without rebinding.
+#[derive(Clone)]
+struct ListenerOptions {
+    tls: Option<TlsAcceptor>,
+    oper_only: bool,
+    proxy: bool,
+}
+
+/// The set of listening sockets. Each one runs its own accept task,
+/// which stops when its options sender is dropped.
+#[derive(Default)]
+pub struct Listeners {
+    running: HashMap<SocketAddr, watch::Sender<ListenerOptions>>,
+}
+
+impl Listeners {
+    /// Starts, stops and updates listeners to match the config.
+    /// Returns a description of each listener that could not be started or updated.
+    pub fn update(&mut self, sss: &SharedServerState, config: &Config) -> Vec<String> {
+        let mut errors = Vec::new();
+        let acceptor = config
+            .tls
+            .as_ref()
+            .and_then(|tls| load_acceptor(tls).map_err(|e| errors.push(e.to_string())).ok());
+
+        self.running.retain(|addr, _| {
+            let keep = config.listeners.iter().any(|l| l.addr == *addr);
+            if !keep {
+                println!("{}", format!("Stopped listening on {}", addr).underline());
+            }
+            keep
+        });
+
+        for listener in &config.listeners {
+            if listener.tls && acceptor.is_none() {
+                errors.push(format!(
+                    "{}: tls is enabled but no certificate was loaded",
+                    listener.addr
+                ));
+                continue;
+            }
+            let options = ListenerOptions {
+                tls: if listener.tls { acceptor.clone() } else { None },
+                oper_only: listener.oper_only,
+                proxy: listener.proxy,
+            };
+            if let Some(sender) = self.running.get(&listener.addr) {
+                sender.send_replace(options);
+                continue;
+            }
+            match bind(listener.addr) {
+                Ok(tcp_listener) => {
+                    let (sender, receiver) = watch::channel(options);
+                    task::spawn(accept_loop(sss.clone(), tcp_listener, receiver));
+                    self.running.insert(listener.addr, sender);
+                    let tls = if listener.tls { " (TLS)" } else { "" };
+                    println!("{}", format!("Listening on {}{}", listener.addr, tls).underline());
+                }
+                Err(e) => errors.push(format!("Could not bind {}: {}", listener.addr, e)),
+            }
+        }
+        errors
+    }
+}
+
+/// IPv6 sockets are bound v6-only, so that [::] and 0.0.0.0 can be listened on separately.
+fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Accepts connections until the options sender is dropped.
+/// Connections that were already accepted are unaffected.
+async fn accept_loop(
+    server: SharedServerState,
+    listener: TcpListener,
+    mut options: watch::Receiver<ListenerOptions>,
+) {
+    loop {
+        let (stream, addr) = tokio::select! {
+            changed = options.changed() => match changed {
+                Ok(()) => continue,
+                Err(_) => break,
+            },
+            conn = listener.accept() => match conn {
+                Ok(conn) => conn,
+                Err(e) => {
+                    eprintln!("Error accepting connection: {}", e);
+                    continue;
+                }
+            },
+        };
+
+        let server_clone = server.clone();
+        let options = options.borrow().clone();
+        task::spawn(async move {
+            if let Err(e) = handle_new_connection(server_clone, stream, addr, options).await {
+                eprintln!("{}", e.red());
+            };
+        });
+    }
+}
+
+/// Reads the PROXY header and performs the TLS handshake if the listener requires them.
+async fn handle_new_connection(
+    server: SharedServerState,
+    mut stream: tokio::net::TcpStream,
+    mut addr: SocketAddr,
+    options: ListenerOptions,
+) -> io::Result<()> {
+    if options.proxy {
+        let header = timeout(HANDSHAKE_TIMEOUT, proxy::read_header(&mut stream))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        if let Some(client_addr) = header {
+            addr = client_addr;
+        }
+    }
+    match options.tls {
+        Some(acceptor) => {
+            let stream = timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+            handle_connection(server, stream, addr, true, options.oper_only).await
+        }
+        None => handle_connection(server, stream, addr, false, options.oper_only).await,
+    }
+}
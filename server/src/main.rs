@@ -1,139 +1,285 @@
+mod ban;
 mod channel;
+mod config;
+mod flood;
+mod line_reader;
+mod listener;
+mod mask;
 mod message_handling;
+mod proxy;
+mod send_queue;
 mod server_state;
+mod tls;
 mod user;
+mod whowas;
 
 use std::{
+    collections::VecDeque,
+    env,
     io::{self},
+    net::SocketAddr,
+    path::PathBuf,
+    process,
     sync::Arc,
+    time::SystemTime,
 };
 
-use ::lazy_static::lazy_static;
 use common::{
     IrcError,
-    message::{Command, Message, Numeric::*},
+    message::{Command, Message, Numeric, Numeric::*},
+    mode::ModeClasses,
 };
 use dns_lookup::lookup_addr;
 use message_handling::handle_message;
 use owo_colors::OwoColorize;
+#[cfg(unix)]
+use tokio::signal::unix::{SignalKind, signal};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::{TcpListener, TcpStream, tcp::OwnedReadHalf},
-    sync::{Mutex, mpsc},
-    task,
+    io::{AsyncRead, AsyncWrite},
+    sync::Mutex,
+    time::{Duration, Instant, sleep_until, timeout_at},
 };
 
 use crate::{
+    config::Config,
+    flood::FloodControl,
+    line_reader::LineReader,
+    mask::wildcard_match,
+    send_queue::SendQueue,
     server_state::{ServerState, SharedServerState},
     user::{SharedUser, User},
 };
 
-const BIND_ADDR: &str = "0.0.0.0:6667";
-
-// ISUPPORT tokens:
-const NICKLEN: usize = 16;
-const TOPICLEN: usize = 307;
-const USERLEN: usize = 10;
-
-pub const USERMODES: &str = "i";
-pub const CHANNELMODES: &str = "s";
-pub const SERVERNAME: &str = "akiRC.chat";
+pub const USERMODES: &str = "ioZ";
+pub const CHANNELMODES: &str = "Ibeiklmnopqstv";
 pub const VERSION: &str = "akiRC_0.3.0";
-pub const CHANNELMODES_WITH_PARAMS: &str = "";
-pub const MOTD: &str = "<3"; // TODO: move to file
-lazy_static! {
-    pub static ref ISUPPORT_TOKENS: [String; 6] = [
-        // String::from("AWAYLEN=200"),
-        // String::from("CASEMAPPING=ascii"),
-        // String::from("CHANLIMIT=#:25"),
-        String::from("CHANMODES=,,,s"),
-        // String::from("CHANNELLEN=32"),
-        String::from("CHANTYPES=#&"), // =#&
-        // String::from("ELIST..."),
-        // String::from("EXCEPTS..."),
-        // String::from("EXTBAN..."),
-        // String::from("HOSTLEN=64"),
-        // String::from("INVEX..."),
-        // String::from("KICKLEN=307"),
-        // String::from("MAXLIST=beI:200"),
-        // String::from("MAXTARGET"),
-        // String::from("MODES=4"),
-        String::from("NETWORK=akiRC"),
-        format!("NICKLEN={}", NICKLEN),
-        // String::from("PREFIX=(ov)@+"),
-        // String::from("SAFELIST"),
-        // String::from("SILENCE"),
-        // String::from("STATUSMSG"),
-        // String::from("TARGMAX=..."),
-        format!("TOPICLEN={}", TOPICLEN),
-        format!("USERLEN={}",   USERLEN),
-    ];
-}
+pub const CHANNELMODES_WITH_PARAMS: &str = "Ibekloqv";
+/// Channel modes without parameters (CHANMODES type D)
+pub const CHANNEL_FLAGS: &str = "imnpst";
+/// Channel modes by how they take parameters, as advertised in CHANMODES and PREFIX.
+pub const CHANNEL_MODE_CLASSES: ModeClasses =
+    ModeClasses { a: "beIq", b: "k", c: "l", d: CHANNEL_FLAGS, prefix: "ov" };
+/// First characters of channel names
+pub const CHANTYPES: &str = "#&";
+/// Prefixes on a channel target that address only its ops (`@`) or voiced members and ops (`+`)
+pub const STATUSMSG: &str = "@+";
 
 #[tokio::main]
 async fn main() {
-    let server = Arc::new(Mutex::new(ServerState::new()));
-    let listener = TcpListener::bind(BIND_ADDR).await.unwrap();
+    let config_path = env::args().nth(1).map(PathBuf::from);
+    let config = match &config_path {
+        Some(path) => Config::load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e.red());
+            process::exit(1);
+        }),
+        None => {
+            println!("No config file given, using defaults.");
+            Config::default()
+        }
+    };
+    let server = Arc::new(Mutex::new(ServerState::new(config, config_path)));
+    let errors = server.lock().await.update_listeners(&server);
+    if !errors.is_empty() {
+        for e in errors {
+            eprintln!("{}", e.red());
+        }
+        process::exit(1);
+    }
 
-    println!(
-        "{}{}{}",
-        SERVERNAME.underline(),
-        " has started on ".underline(),
-        BIND_ADDR.underline()
-    );
-    loop {
-        let (stream, _) = match listener.accept().await {
-            Ok(conn) => conn,
-            Err(e) => {
-                eprintln!("Error accepting connection: {}", e);
-                continue;
-            }
-        };
+    #[cfg(unix)]
+    rehash_on_sighup(server).await;
+    #[cfg(not(unix))]
+    std::future::pending::<()>().await;
+}
 
-        let server_clone = server.clone();
-        task::spawn(async move {
-            if let Err(e) = handle_connection(server_clone, stream).await {
-                eprintln!("{}", e.red());
-            };
-        });
+/// Reloads the config whenever the process receives SIGHUP, and tells opers about it.
+#[cfg(unix)]
+async fn rehash_on_sighup(sss: SharedServerState) {
+    let mut hangup = signal(SignalKind::hangup()).expect("failed to listen for SIGHUP");
+    while hangup.recv().await.is_some() {
+        let mut server = sss.lock().await;
+        let notices = match server.rehash(&sss) {
+            Ok(not_applied) => std::iter::once(String::from("*** Got SIGHUP, config reloaded"))
+                .chain(not_applied.into_iter().map(|change| format!("*** Rehash: {}", change)))
+                .collect::<Vec<_>>(),
+            Err(e) => vec![format!("*** Got SIGHUP, rehash failed: {}", e)],
+        };
+        for notice in &notices {
+            println!("{}", notice.yellow());
+        }
+        for oper in server.users().filter(|u| u.is_oper()) {
+            for notice in &notices {
+                oper.notice(notice).await;
+            }
+        }
     }
 }
 
 enum MaybeReg {
-    Unreg(User),
+    Unreg(Box<User>),
     Reg(SharedUser),
+    /// The client was refused registration and is being disconnected.
+    Rejected,
 }
 
-async fn handle_connection(server: SharedServerState, stream: TcpStream) -> io::Result<()> {
-    let addr = stream.peer_addr()?;
+/// Drives one client connection. `secure` is true if the stream is TLS.
+/// If `oper_only` is true, only clients that match an oper block may register.
+async fn handle_connection(
+    server: SharedServerState,
+    stream: impl AsyncRead + AsyncWrite + Send + 'static,
+    addr: SocketAddr,
+    secure: bool,
+    oper_only: bool,
+) -> io::Result<()> {
     println!("{} {} Looking up hostname...", "Connected:".green(), addr);
 
-    let (reader, mut writer) = stream.into_split();
-    let (tx, mut rx) = mpsc::channel::<Arc<Message>>(100);
-
-    tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            println!("{}", format!("-> {msg}").truecolor(100, 110, 135));
-            if let Err(e) = writer.write_all((msg.to_string() + "\r\n").as_bytes()).await {
-                eprintln!("Write error: {}", e);
-                break;
-            }
-        }
-    });
-
-    let mut buf_reader = BufReader::new(reader);
-    let mut buffer = String::new();
+    let (reader, writer) = tokio::io::split(stream);
+    let mut reader = LineReader::new(reader);
 
     let ip = addr.ip();
     // todo: Ident
-    let hostname = lookup_addr(&ip).unwrap_or(ip.to_string());
+    let lookup = lookup_addr(&ip).ok();
+    let host_notice = match lookup {
+        Some(_) => "*** Found your hostname",
+        None => "*** Couldn't look up your hostname, using your IP address instead",
+    };
+    let mut hostname = lookup.unwrap_or(ip.to_string());
+    if hostname.starts_with(':') {
+        // a leading ':' would be read as the start of a trailing parameter, e.g. "::1"
+        hostname.insert(0, '0');
+    }
+    let (servername, timeouts, class) = {
+        let server = server.lock().await;
+        let config = &server.config;
+        (
+            config.servername.clone(),
+            config.timeouts.clone(),
+            config.class_for(&hostname, &ip.to_string()),
+        )
+    };
+
+    println!("{} {} is {} in class {}", "Connected:".green(), addr, hostname, class.name);
+
+    let new_user =
+        User::new(SendQueue::spawn(writer, class.sendq), Some(ip), hostname, servername.clone());
+    if secure {
+        new_user.add_mode('Z');
+    }
+    new_user.notice(host_notice).await;
+    let mut closed = new_user.subscribe_close();
+    let mut user = MaybeReg::Unreg(Box::new(new_user));
 
-    let mut user = MaybeReg::Unreg(User::new(tx, hostname));
+    let registration_deadline = Instant::now() + Duration::from_secs(timeouts.registration);
+    let ping_interval = Duration::from_secs(timeouts.ping_interval);
+    let ping_timeout = Duration::from_secs(timeouts.ping_timeout);
+    let mut last_activity = Instant::now();
+    let mut flood = FloodControl::new(&class, last_activity);
+    // Lines are read as soon as they arrive, but wait here until flood control lets them through.
+    let mut delayed: VecDeque<(Instant, Result<Message, IrcError>)> = VecDeque::new();
+    // cleared once the connection has closed or failed
+    let mut reading = true;
+    // when our PING was sent, if it has not been answered yet
+    let mut ping_sent: Option<Instant> = None;
 
     loop {
-        match next_message(&mut buf_reader, &mut buffer).await {
+        let deadline = match (&user, ping_sent) {
+            (MaybeReg::Unreg(_), _) => registration_deadline,
+            (_, Some(sent)) => sent + ping_timeout,
+            (_, None) => last_activity + ping_interval,
+        };
+        let next_due = delayed.front().map(|(due, _)| *due);
+        let next = tokio::select! {
+            biased;
+            Ok(()) = closed.changed() => {
+                let reason = closed.borrow().clone().unwrap_or_default();
+                println!("{} {} ({})", "Closed:".red(), addr, reason);
+                match user {
+                    MaybeReg::Unreg(u) => server.lock().await.remove_unregistered_nick(*u),
+                    MaybeReg::Reg(ref u) => drop_user(&server, u, &reason).await,
+                    MaybeReg::Rejected => (),
+                }
+                return Ok(());
+            }
+            _ = sleep_until(next_due.unwrap_or(deadline)), if next_due.is_some() => {
+                delayed.pop_front().unwrap().1
+            }
+            next = timeout_at(deadline, reader.next_message()), if reading => {
+                let Ok(next) = next else {
+                    match user {
+                        MaybeReg::Unreg(u) => {
+                            println!("{} {}", "Registration timeout:".red(), addr);
+                            drop_unregistered(&server, *u, "Registration timed out").await;
+                            return Ok(());
+                        }
+                        MaybeReg::Reg(ref u) if ping_sent.is_some() => {
+                            println!("{} {}", "Ping timeout:".red(), addr);
+                            let seconds = (ping_interval + ping_timeout).as_secs();
+                            drop_user(&server, u, &format!("Ping timeout: {} seconds", seconds))
+                                .await;
+                            return Ok(());
+                        }
+                        MaybeReg::Reg(ref u) => {
+                            u.send(Arc::new(Message::new(
+                                Some(&servername),
+                                Command::PING { token: servername.clone() },
+                            )))
+                            .await;
+                            ping_sent = Some(Instant::now());
+                            continue;
+                        }
+                        MaybeReg::Rejected => unreachable!(),
+                    }
+                };
+                last_activity = Instant::now();
+                // any line shows the client is alive, even one waiting behind flood control,
+                // except a PONG to something other than our PING
+                let stray_pong = matches!(
+                    &next,
+                    Ok(Message { command: Command::PONG { token, .. }, .. }) if *token != servername
+                );
+                if !stray_pong {
+                    ping_sent = None;
+                }
+
+                let mut due = last_activity;
+                if let Err(IrcError::Io(_)) = next {
+                    reading = false;
+                } else if !class.flood_exempt
+                    && !matches!(&user, MaybeReg::Reg(u) if u.is_oper())
+                {
+                    let Some(delay) = flood.take(last_activity) else {
+                        println!("{} {}", "Excess Flood:".red(), addr);
+                        match user {
+                            MaybeReg::Unreg(u) => {
+                                drop_unregistered(&server, *u, "Excess Flood").await
+                            }
+                            MaybeReg::Reg(ref u) => drop_user(&server, u, "Excess Flood").await,
+                            MaybeReg::Rejected => (),
+                        }
+                        return Ok(());
+                    };
+                    due += delay;
+                }
+                if delayed.is_empty() && due <= last_activity {
+                    next
+                } else {
+                    // keeps the lines in order, even if an exempt user's line has no delay
+                    let due = delayed.back().map_or(due, |(last, _)| due.max(*last));
+                    delayed.push_back((due, next));
+                    continue;
+                }
+            }
+        };
+
+        match next {
             Ok(msg) => match user {
-                MaybeReg::Unreg(u) => user = handle_message_and_try_register(&server, u, msg).await,
+                MaybeReg::Unreg(u) => {
+                    user = handle_message_and_try_register(&server, u, msg, oper_only).await;
+                    if let MaybeReg::Rejected = user {
+                        println!("{} {}", "Rejected:".red(), addr);
+                        return Ok(());
+                    }
+                }
                 MaybeReg::Reg(ref u) => {
                     let quit = matches!(&msg.command, Command::QUIT { .. });
                     handle_message(&server, u, msg).await;
@@ -143,13 +289,15 @@ async fn handle_connection(server: SharedServerState, stream: TcpStream) -> io::
                         return Ok(());
                     }
                 }
+                MaybeReg::Rejected => unreachable!(),
             },
             Err(IrcError::IrcParseError(e)) => println!("{}", e.bright_purple()),
             Err(IrcError::Io(e)) => {
                 println!("{} {} [{}] {e}", "Disconnected:".red(), addr, e.kind());
                 match user {
-                    MaybeReg::Unreg(u) => server.lock().await.remove_unregistered_nick(u),
-                    MaybeReg::Reg(u) => server.lock().await.remove_user(u),
+                    MaybeReg::Unreg(u) => server.lock().await.remove_unregistered_nick(*u),
+                    MaybeReg::Reg(ref u) => drop_user(&server, u, "Connection closed").await,
+                    MaybeReg::Rejected => (),
                 }
                 return Ok(());
             }
@@ -157,10 +305,39 @@ async fn handle_connection(server: SharedServerState, stream: TcpStream) -> io::
     }
 }
 
+/// Sends an ERROR to a client that has not registered and frees its nickname.
+async fn drop_unregistered(server: &SharedServerState, user: User, reason: &str) {
+    user.send(Arc::new(Message::new(
+        Some(&user.servername),
+        Command::ERROR { reason: format!("Closing Link: {} ({})", user.hostname, reason) },
+    )))
+    .await;
+    server.lock().await.remove_unregistered_nick(user);
+}
+
+/// Broadcasts a QUIT for a user whose connection is being closed by the server,
+/// sends them an ERROR and removes them from the server.
+async fn drop_user(server: &SharedServerState, user: &SharedUser, reason: &str) {
+    let mut server = server.lock().await;
+    let fqn = user.get_fqn_string();
+    user.broadcast(
+        false,
+        Arc::new(Message::new(Some(&fqn), Command::QUIT { reason: reason.to_owned() })),
+    )
+    .await;
+    user.send(Arc::new(Message::new(
+        Some(&user.servername),
+        Command::ERROR { reason: format!("Closing Link: {} ({})", fqn, reason) },
+    )))
+    .await;
+    server.remove_user(user.clone());
+}
+
 async fn handle_message_and_try_register(
     server: &SharedServerState,
-    mut user: User,
+    mut user: Box<User>,
     message: Message,
+    oper_only: bool,
 ) -> MaybeReg {
     let mut server_lock = server.lock().await;
 
@@ -175,11 +352,14 @@ async fn handle_message_and_try_register(
         }
         Command::USER { username, _1, _2, realname } => {
             // TODO: restrict to alphanum?
-            let username: String = username.chars().take(USERLEN - 1).collect();
+            let userlen = server_lock.config.limits.userlen;
+            let username: String = username.chars().take(userlen - 1).collect();
             user.username = format!("~{username}");
             user.realname = realname;
         }
-        Command::Invalid(nick, Some(num), s) if ["NICK", "USER"].contains(&nick.as_str()) => {
+        Command::Invalid(cmd, Some(num), s)
+            if ["NICK", "USER"].contains(&cmd.as_str()) || num == ERR_INPUTTOOLONG =>
+        {
             user.reply(num, &s).await;
         }
         _ => println!("Ignoring message from unregistered user: ({})", message),
@@ -189,7 +369,23 @@ async fn handle_message_and_try_register(
         return MaybeReg::Unreg(user);
     }
 
-    let user = server_lock.register_user(user);
+    let fqn = user.get_fqn_string();
+    let userhost = format!("{}@{}", user.username, user.hostname);
+    if let Some((numeric, params, reason)) =
+        registration_rejection(&server_lock.config, &fqn, &userhost, oper_only)
+    {
+        user.reply(numeric, &params).await;
+        user.send(Arc::new(Message::new(
+            Some(&user.servername),
+            Command::ERROR { reason: format!("Closing Link: {} ({})", fqn, reason) },
+        )))
+        .await;
+        server_lock.remove_unregistered_nick(*user);
+        return MaybeReg::Rejected;
+    }
+
+    user.signon = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
+    let user = server_lock.register_user(*user);
 
     user.reply(
         RPL_WELCOME,
@@ -201,57 +397,78 @@ async fn handle_message_and_try_register(
         ),
     )
     .await;
-    user.reply(RPL_YOURHOST, &format!(":Your host is {}, running version {}", SERVERNAME, VERSION))
-        .await;
+    user.reply(
+        RPL_YOURHOST,
+        &format!(":Your host is {}, running version {}", user.servername, VERSION),
+    )
+    .await;
     user.reply(RPL_CREATED, &format!(":This server was created {}", server_lock.creation_datetime))
         .await;
     user.reply(
         RPL_MYINFO,
         &format!(
             "{} {} {} {} {}",
-            SERVERNAME, VERSION, USERMODES, CHANNELMODES, CHANNELMODES_WITH_PARAMS
+            user.servername, VERSION, USERMODES, CHANNELMODES, CHANNELMODES_WITH_PARAMS
         ),
     )
     .await;
-    assert!(ISUPPORT_TOKENS.len() <= 13, "write logic for splitting messages");
-    user.reply(
-        RPL_ISUPPORT,
-        &format!("{} :are supported by this server", ISUPPORT_TOKENS.join(" ")),
-    )
-    .await;
+    let isupport_tokens = server_lock.config.isupport_tokens();
+    // 13 tokens leave room for the nickname and the trailing text in the 15 parameters allowed
+    user.reply_list(RPL_ISUPPORT, "", &isupport_tokens, " :are supported by this server", 13).await;
     // Other numerics/messages
     // LUSERS responses
     // MOTD
+    drop(server_lock);
     handle_message(server, &user, Message::new(None, Command::MOTD { target: String::new() }))
         .await;
     // UMODEIS or MODE
     MaybeReg::Reg(user)
 }
 
-async fn next_message(
-    reader: &mut BufReader<OwnedReadHalf>,
-    // reader: &mut (impl AsyncBufReadExt + Unpin),
-    buffer: &mut String,
-) -> Result<Message, IrcError> {
-    loop {
-        if let Some(pos_rn) = buffer.find(['\r', '\n']) {
-            let mut msg_str: String = buffer.drain(..(pos_rn + 1)).collect();
-            msg_str.pop();
-            if !msg_str.is_empty() {
-                let res: Result<Message, IrcError> = msg_str.parse();
-                match &res {
-                    Ok(m) if matches!(m, Message { command: Command::Invalid(..), .. }) => {
-                        println!("{}", format!("<- [{msg_str}] -- {m}").bright_purple())
-                    }
-                    Ok(_) => {
-                        println!("{}", format!("<- {msg_str}").bright_blue())
-                    }
-                    Err(err) => println!("{}", format!("<- [{msg_str}] -- {err}").bright_red()),
-                }
-                return res;
-            }
-        } else if reader.read_line(buffer).await? == 0 {
-            return Err(IrcError::Io(io::Error::from(io::ErrorKind::UnexpectedEof)));
-        }
+/// The numeric, its parameters and the ERROR reason for a client that may not register,
+/// either because a server ban matches it or because it is on an oper-only listener
+/// without a matching oper host.
+fn registration_rejection(
+    config: &Config,
+    fqn: &str,
+    userhost: &str,
+    oper_only: bool,
+) -> Option<(Numeric, String, &'static str)> {
+    if let Some(ban) = config.bans.iter().find(|ban| wildcard_match(&ban.mask, fqn)) {
+        Some((
+            ERR_YOUREBANNEDCREEP,
+            format!(":You are banned from this server ({})", ban.reason),
+            "Banned",
+        ))
+    } else if oper_only && !config.oper_host_allowed(userhost) {
+        Some((ERR_NOOPERHOST, String::from(":No O-lines for your host"), "Oper-only port"))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oper_only_rejection() {
+        let config: Config = toml::from_str(
+            r#"
+            [[oper]]
+            name = "anywhere"
+            password = "p"
+            [[oper]]
+            name = "local"
+            password = "p"
+            host = "*@localhost"
+            "#,
+        )
+        .unwrap();
+        // an oper block without a host does not open the listener to every client
+        let rejection = registration_rejection(&config, "a!~a@remote", "~a@remote", true);
+        assert_eq!(rejection.map(|(numeric, ..)| numeric), Some(ERR_NOOPERHOST));
+        assert!(registration_rejection(&config, "a!~a@localhost", "~a@localhost", true).is_none());
+        assert!(registration_rejection(&config, "a!~a@remote", "~a@remote", false).is_none());
     }
 }
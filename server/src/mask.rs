@@ -0,0 +1,73 @@
+/// Matches `s` against a wildcard mask, where `*` matches any number of characters and `?` matches
+/// exactly one. Comparison is ASCII case-insensitive.
+pub fn wildcard_match(mask: &str, s: &str) -> bool {
+    let mask: Vec<char> = mask.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let s: Vec<char> = s.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let (mut m, mut i) = (0, 0);
+    // position of the last '*' in mask, and the position in s it is currently matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while i < s.len() {
+        if m < mask.len() && (mask[m] == '?' || mask[m] == s[i]) {
+            m += 1;
+            i += 1;
+        } else if m < mask.len() && mask[m] == '*' {
+            backtrack = Some((m, i));
+            m += 1;
+        } else if let Some((star_m, star_i)) = backtrack {
+            // let the last '*' consume one more character
+            backtrack = Some((star_m, star_i + 1));
+            m = star_m + 1;
+            i = star_i + 1;
+        } else {
+            return false;
+        }
+    }
+    mask[m..].iter().all(|&c| c == '*')
+}
+
+/// Completes a ban-style mask to `nick!user@host`. A bare word is taken as a nick,
+/// unless it contains a `.` or `:`, in which case it is taken as a host.
+pub fn normalize_mask(mask: &str) -> String {
+    let (nick, userhost) = match mask.split_once('!') {
+        Some((nick, userhost)) => (nick, Some(userhost)),
+        None if mask.contains('@') => ("*", Some(mask)),
+        None if mask.contains(['.', ':']) => return format!("*!*@{mask}"),
+        None => (mask, None),
+    };
+    let (user, host) = match userhost.map(|uh| uh.split_once('@').unwrap_or((uh, "*"))) {
+        Some((user, host)) => (user, host),
+        None => ("*", "*"),
+    };
+    let or_star = |s: &str| if s.is_empty() { "*".to_string() } else { s.to_string() };
+    format!("{}!{}@{}", or_star(nick), or_star(user), or_star(host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*", "nick!user@host"));
+        assert!(wildcard_match("nick!*@*", "nick!~user@host.example.com"));
+        assert!(wildcard_match("*!*@*.EXAMPLE.com", "nick!user@host.example.com"));
+        assert!(wildcard_match("n?ck!*", "nIck!user@host"));
+        assert!(wildcard_match("*a*b*c", "xxaxxbxxbxxc"));
+        assert!(!wildcard_match("n?ck!*", "nck!user@host"));
+        assert!(!wildcard_match("*!*@*.example.com", "nick!user@example.com"));
+        assert!(!wildcard_match("nick", "nickname"));
+        assert!(!wildcard_match("", "nick"));
+    }
+
+    #[test]
+    fn test_normalize_mask() {
+        assert_eq!(normalize_mask("nick"), "nick!*@*");
+        assert_eq!(normalize_mask("nick!user"), "nick!user@*");
+        assert_eq!(normalize_mask("user@host"), "*!user@host");
+        assert_eq!(normalize_mask("*.example.com"), "*!*@*.example.com");
+        assert_eq!(normalize_mask("nick!@host"), "nick!*@host");
+        assert_eq!(normalize_mask("n!u@h"), "n!u@h");
+    }
+}
@@ -1,9 +1,17 @@
 use std::sync::Arc;
 
-use common::message::{Command, Message, Numeric::*};
+use common::{
+    message::{Command, Message, Numeric, Numeric::*},
+    mode::{ModeChange, ModeClasses, format_mode_changes, parse_mode_changes},
+    parse::is_valid_key,
+};
 
 use crate::{
-    channel::ChannelModes, server_state::SharedServerState, user::{SharedUser, User}, CHANNELMODES, MOTD, NICKLEN, SERVERNAME, TOPICLEN, USERMODES
+    CHANNEL_FLAGS, CHANNEL_MODE_CLASSES, CHANTYPES, STATUSMSG, USERMODES, ban,
+    channel::{ChannelModes, Membership, SharedChannel},
+    mask::{normalize_mask, wildcard_match},
+    server_state::{ServerState, SharedServerState},
+    user::{SharedUser, User},
 };
 
 /// Handles one message for a registered user.
@@ -21,7 +29,7 @@ pub async fn handle_message(server: &SharedServerState, user: &SharedUser, messa
         }
         PING { token } => handle_PING(server, user, token).await,
         PONG { server: _, token: _ } => (),
-        // OPER
+        OPER { name, password } => handle_OPER(server, user, name, password).await,
         QUIT { reason } => handle_QUIT(server, user, reason).await,
         // QUIT { reason } => handle_QUIT(server, user, reason).await,
         ERROR { reason: _ } => (),
@@ -30,10 +38,10 @@ pub async fn handle_message(server: &SharedServerState, user: &SharedUser, messa
         JOIN { channels, keys, alt } => handle_JOIN(server, user, channels, keys, alt).await,
         PART { channels, reason } => handle_PART(server, user, channels, reason).await,
         TOPIC { channel, topic } => handle_TOPIC(server, user, channel, topic).await,
-        // NAMES
+        NAMES { channels } => handle_NAMES(server, user, channels).await,
         LIST { channels, elistconds } => handle_LIST(server, user, channels, elistconds).await,
-        // INVITE
-        // KICK
+        INVITE { nickname, channel } => handle_INVITE(server, user, nickname, channel).await,
+        KICK { channel, users, reason } => handle_KICK(server, user, channel, users, reason).await,
 
         /* Server Queries and Commands */
         MOTD { target } => handle_MOTD(server, user, target).await,
@@ -51,23 +59,26 @@ pub async fn handle_message(server: &SharedServerState, user: &SharedUser, messa
 
         /* Sending Messages */
         PRIVMSG { targets, text } => handle_PRIVMSG(server, user, targets, text).await,
-        // NOTICE
+        NOTICE { targets, text } => handle_NOTICE(server, user, targets, text).await,
 
         /* User Based Queries */
-        WHO { mask } => handle_WHO(server, user, mask).await,
-        // WHOIS
-        // WHOWAS
+        WHO { mask, opers_only, fields, token } => {
+            handle_WHO(server, user, mask, opers_only, fields, token).await
+        }
+        WHOIS { target, nicks } => handle_WHOIS(server, user, target, nicks).await,
+        WHOWAS { nick, count } => handle_WHOWAS(server, user, nick, count).await,
 
         /* Operator Messages */
         // KILL
-        // REHASH
+        REHASH => handle_REHASH(server, user).await,
         // RESTART
         // SQUIT
 
         /* Optional Messages */
-        // AWAY
+        AWAY { text } => handle_AWAY(server, user, text).await,
+        ISON { nicknames } => handle_ISON(server, user, nicknames).await,
         // LINKS
-        // USERHOST
+        USERHOST { nicknames } => handle_USERHOST(server, user, nicknames).await,
         // WALLOPS
 
         /* Other */
@@ -93,9 +104,17 @@ type Res = ();
 #[allow(non_snake_case)]
 async fn handle_NICK(sss: &Sss, su: &Su, a_nick: String) -> Res {
     let mut server = sss.lock().await;
-    let a_nick = a_nick.chars().take(NICKLEN).collect::<String>();
+    let a_nick = a_nick.chars().take(server.config.limits.nicklen).collect::<String>();
     let target = su.get_fqn_string();
-    if server.try_update_nick(su, &a_nick) {
+    if let Some(channel) =
+        su.get_channels().find(|channel| is_banned_and_unvoiced(&server, channel, su))
+    {
+        su.reply(
+            ERR_BANNICKCHANGE,
+            &format!("{} {} :Cannot change nickname while banned on channel", a_nick, channel.name),
+        )
+        .await
+    } else if server.try_update_nick(su, &a_nick) {
         su.broadcast(
             true,
             Arc::new(Message::new(Some(&target), Command::NICK { nickname: a_nick })),
@@ -108,23 +127,50 @@ async fn handle_NICK(sss: &Sss, su: &Su, a_nick: String) -> Res {
 #[allow(non_snake_case)]
 async fn handle_PING(_sss: &Sss, su: &Su, a_token: String) -> Res {
     su.send(Arc::new(Message::new(
-        Some(SERVERNAME),
-        Command::PONG { server: SERVERNAME.to_string(), token: a_token },
+        Some(&su.servername),
+        Command::PONG { server: su.servername.clone(), token: a_token },
     )))
     .await;
 }
 // PONG (ignored)
-// OPER
+#[allow(non_snake_case)]
+async fn handle_OPER(sss: &Sss, su: &Su, a_name: String, a_password: String) -> Res {
+    let server = sss.lock().await;
+    let Some(oper) = server.config.opers.iter().find(|o| o.name == a_name) else {
+        su.reply(ERR_PASSWDMISMATCH, ":Password incorrect").await;
+        return;
+    };
+    if !oper.host_matches(&format!("{}@{}", su.username, su.hostname)) {
+        su.reply(ERR_NOOPERHOST, ":No O-lines for your host").await
+    } else if oper.password != a_password {
+        su.reply(ERR_PASSWDMISMATCH, ":Password incorrect").await
+    } else {
+        su.reply(RPL_YOUREOPER, ":You are now an IRC operator").await;
+        if su.add_mode('o') {
+            su.send(Arc::new(Message::new(
+                Some(&su.get_fqn_string()),
+                Command::MODE {
+                    target: su.get_nickname(),
+                    modestring: String::from("+o"),
+                    modeargs: vec![],
+                },
+            )))
+            .await
+        }
+    }
+}
 #[allow(non_snake_case)]
 async fn handle_QUIT(_sss: &Sss, su: &Su, a_reason: String) -> Res {
-    su.broadcast(true, Arc::new(Message::new(
-        Some(&su.get_fqn_string()),
-        Command::QUIT { reason: a_reason },
-    ))).await;
+    su.broadcast(
+        true,
+        Arc::new(Message::new(Some(&su.get_fqn_string()), Command::QUIT { reason: a_reason })),
+    )
+    .await;
     su.send(Arc::new(Message::new(
-        Some(SERVERNAME),
-        Command::ERROR { reason: format!(":Closing Link: {} (Client Quit)", su.get_fqn_string()) },
-    ))).await;
+        Some(&su.servername),
+        Command::ERROR { reason: format!("Closing Link: {} (Client Quit)", su.get_fqn_string()) },
+    )))
+    .await;
 }
 // ERROR (ignored)
 
@@ -134,34 +180,66 @@ async fn handle_JOIN(
     sss: &Sss,
     user: &Su,
     a_channels: Vec<String>,
-    _a_keys: Vec<String>, // todo
-    _a_flag: bool,        // todo
+    a_keys: Vec<String>,
+    _a_flag: bool, // todo
 ) -> Res {
     let mut server = sss.lock().await;
-    // todo: join multiple channels
-    let channel_name = a_channels[0].clone();
-    let channel =
-        server.get_channel(&channel_name).unwrap_or_else(|| server.create_channel(&channel_name));
-    server.add_user_to_channel(user, &channel);
-    let nicks = channel.get_users().map(|u| u.get_nickname()).collect::<Vec<_>>();
-    channel
-        .broadcast(Arc::new(Message::new(
-            Some(&user.get_fqn_string()),
-            Command::JOIN { channels: vec![channel_name.clone()], keys: vec![], alt: false },
-        )))
-        .await;
-    if let Some((topic, who, time)) = channel.get_topic_info() {
-        user.reply(RPL_TOPIC, &format!("{} :{}", channel_name, topic)).await;
-        user.reply(RPL_TOPICWHOTIME, &format!("{} {} {}", channel_name, who, time)).await;
-    } else {
-        user.reply(RPL_NOTOPIC, &format!("{} :No topic is set", channel_name)).await;
+    for (index, channel_name) in a_channels.into_iter().enumerate() {
+        if !is_valid_channel_name(&channel_name) {
+            user.reply(ERR_NOSUCHCHANNEL, &format!("{} :No such channel", channel_name)).await;
+            continue;
+        }
+        let (channel, created) = match server.get_channel(&channel_name) {
+            Some(channel) => (channel, false),
+            None => (server.create_channel(&channel_name), true),
+        };
+        if channel.contains_user(user) {
+            continue;
+        }
+        let modes = channel.get_modes();
+        // the creator is never rejected, not even by default modes such as +i,
+        // so a rejected JOIN never leaves an empty channel behind
+        let rejection = if created {
+            None
+        } else if modes.i
+            && !channel.is_invited(user)
+            && !ban::is_invite_exempt(&server, &channel, user)
+        {
+            Some((ERR_INVITEONLYCHAN, 'i'))
+        } else if ban::is_banned(&server, &channel, user, 'b') {
+            Some((ERR_BANNEDFROMCHAN, 'b'))
+        } else if modes.k.is_some() && modes.k.as_ref() != a_keys.get(index) {
+            Some((ERR_BADCHANNELKEY, 'k'))
+        } else if modes.l.is_some_and(|limit| channel.user_count() >= limit) {
+            Some((ERR_CHANNELISFULL, 'l'))
+        } else {
+            None
+        };
+        if let Some((numeric, mode)) = rejection {
+            user.reply(numeric, &format!("{} :Cannot join channel (+{})", channel_name, mode))
+                .await;
+            continue;
+        }
+        channel.remove_invite(user);
+        server.add_user_to_channel(user, &channel);
+        if created {
+            channel.set_member_mode(user, 'o', true);
+        }
+        channel
+            .broadcast(Arc::new(Message::new(
+                Some(&user.get_fqn_string()),
+                Command::JOIN { channels: vec![channel_name.clone()], keys: vec![], alt: false },
+            )))
+            .await;
+        if let Some((topic, who, time)) = channel.get_topic_info() {
+            user.reply(RPL_TOPIC, &format!("{} :{}", channel_name, topic)).await;
+            user.reply(RPL_TOPICWHOTIME, &format!("{} {} {}", channel_name, who, time)).await;
+        } else {
+            user.reply(RPL_NOTOPIC, &format!("{} :No topic is set", channel_name)).await;
+        }
+        send_names(user, &channel).await;
+        user.reply(RPL_ENDOFNAMES, &format!("{} :End of /NAMES list", channel_name)).await;
     }
-    user.reply(
-        RPL_NAMREPLY,
-        &format!("= {} :{}", channel_name, nicks.join(" ")), // todo: message limit
-    )
-    .await;
-    user.reply(RPL_ENDOFNAMES, &format!("{} :End of /NAMES list", channel_name)).await;
 }
 #[allow(non_snake_case)]
 async fn handle_PART(sss: &Sss, user: &Su, a_channels: Vec<String>, a_reason: String) -> Res {
@@ -186,10 +264,17 @@ async fn handle_PART(sss: &Sss, user: &Su, a_channels: Vec<String>, a_reason: St
 #[allow(non_snake_case)]
 async fn handle_TOPIC(sss: &Sss, user: &Su, a_channel: String, a_topic: Option<String>) -> Res {
     let server = sss.lock().await;
-    let a_topic = a_topic.map(|s| s.chars().take(TOPICLEN).collect::<String>());
+    let a_topic =
+        a_topic.map(|s| s.chars().take(server.config.limits.topiclen).collect::<String>());
     if let Some(channel) = server.get_channel(&a_channel) {
         if channel.contains_user(user) {
-            if let Some(topic) = a_topic {
+            if a_topic.is_some() && channel.get_modes().t && !channel.is_op(user) {
+                user.reply(
+                    ERR_CHANOPRIVSNEEDED,
+                    &format!("{} :You're not a channel operator", a_channel),
+                )
+                .await;
+            } else if let Some(topic) = a_topic {
                 channel.set_topic(user, &topic);
                 channel
                     .broadcast(Arc::new(Message::new(
@@ -210,7 +295,126 @@ async fn handle_TOPIC(sss: &Sss, user: &Su, a_channel: String, a_topic: Option<S
         user.reply(ERR_NOSUCHCHANNEL, ":No such channel").await;
     }
 }
-// NAMES
+#[allow(non_snake_case)]
+async fn handle_NAMES(sss: &Sss, user: &Su, a_channels: Vec<String>) -> Res {
+    let server = sss.lock().await;
+    if a_channels.is_empty() {
+        for channel in server.get_channels() {
+            send_names(user, &channel).await;
+        }
+        let nicks = channelless_nicks(&server, user);
+        if !nicks.is_empty() {
+            user.reply_list(RPL_NAMREPLY, "* * :", &nicks, "", usize::MAX).await;
+        }
+        user.reply(RPL_ENDOFNAMES, "* :End of /NAMES list").await;
+    }
+    for channel_name in a_channels {
+        if let Some(channel) = server.get_channel(&channel_name) {
+            send_names(user, &channel).await;
+        }
+        user.reply(RPL_ENDOFNAMES, &format!("{} :End of /NAMES list", channel_name)).await;
+    }
+}
+/// Users that NAMES without a channel lists under `*`: those who are not invisible
+/// and are in no channel that `user` can see.
+fn channelless_nicks(server: &ServerState, user: &Su) -> Vec<String> {
+    server
+        .users()
+        .filter(|other| !other.is_invisible())
+        .filter(|other| {
+            !other.get_channels().any(|channel| {
+                let modes = channel.get_modes();
+                !(modes.s || modes.p) || channel.contains_user(user)
+            })
+        })
+        .map(|other| other.get_nickname())
+        .collect()
+}
+/// Sends the RPL_NAMREPLY lines of a channel, but not RPL_ENDOFNAMES.
+/// Non-members see nothing of a secret or private channel, and only the members that are not invisible.
+async fn send_names(user: &Su, channel: &SharedChannel) {
+    let is_member = channel.contains_user(user);
+    let modes = channel.get_modes();
+    if (modes.s || modes.p) && !is_member {
+        return;
+    }
+    let nicks: Vec<String> = channel
+        .get_members()
+        .into_iter()
+        .filter(|(member, _)| is_member || !member.is_invisible())
+        .map(|(member, membership)| format!("{}{}", membership.prefix(), member.get_nickname()))
+        .collect();
+    if nicks.is_empty() {
+        return;
+    }
+    let symbol = match (modes.s, modes.p) {
+        (true, _) => '@',
+        (_, true) => '*',
+        _ => '=',
+    };
+    let head = format!("{} {} :", symbol, channel.name);
+    user.reply_list(RPL_NAMREPLY, &head, &nicks, "", usize::MAX).await;
+}
+/// Sends the entries of a list mode. Exception lists are only shown to channel operators,
+/// and non-members of a secret or private channel get only the end of the list.
+async fn send_list(user: &Su, channel: &SharedChannel, mode: char) {
+    let (entry_numeric, end_numeric, what) = match mode {
+        'b' => (RPL_BANLIST, RPL_ENDOFBANLIST, "ban"),
+        'e' => (RPL_EXCEPTLIST, RPL_ENDOFEXCEPTLIST, "exception"),
+        'q' => (RPL_QUIETLIST, RPL_ENDOFQUIETLIST, "quiet"),
+        _ => (RPL_INVEXLIST, RPL_ENDOFINVEXLIST, "invite exception"),
+    };
+    if !matches!(mode, 'b' | 'q') && !channel.is_op(user) {
+        user.reply(
+            ERR_CHANOPRIVSNEEDED,
+            &format!("{} :You're not a channel operator", channel.name),
+        )
+        .await;
+        return;
+    }
+    // the quiet list repeats the mode letter, as other servers do
+    let mode_param = if mode == 'q' { " q" } else { "" };
+    let modes = channel.get_modes();
+    let entries = if (modes.s || modes.p) && !channel.contains_user(user) {
+        vec![]
+    } else {
+        channel.get_list(mode)
+    };
+    for entry in entries {
+        user.reply(
+            entry_numeric,
+            &format!(
+                "{}{} {} {} {}",
+                channel.name, mode_param, entry.mask, entry.setter, entry.time
+            ),
+        )
+        .await;
+    }
+    user.reply(end_numeric, &format!("{} :End of channel {} list", channel.name, what)).await;
+}
+/// Whether a channel called `name` may be created: a CHANTYPES prefix,
+/// then no spaces, commas, colons or control characters.
+fn is_valid_channel_name(name: &str) -> bool {
+    name.starts_with(|c| CHANTYPES.contains(c))
+        && !name.contains(|c: char| c.is_ascii_control() || " ,:".contains(c))
+}
+/// Checks +n, +m, bans and quiets for a message to the channel.
+fn can_send(server: &ServerState, channel: &SharedChannel, user: &Su) -> bool {
+    let modes = channel.get_modes();
+    match channel.get_membership(user) {
+        None => !modes.n && !modes.m && !is_silenced(server, channel, user),
+        Some(membership) if membership.op || membership.voice => true,
+        Some(_) => !modes.m && !is_silenced(server, channel, user),
+    }
+}
+/// Banned or quieted members can still speak and change nicks once they are voiced or opped.
+fn is_banned_and_unvoiced(server: &ServerState, channel: &SharedChannel, user: &Su) -> bool {
+    let privileged = channel.get_membership(user).is_some_and(|m| m.op || m.voice);
+    !privileged && is_silenced(server, channel, user)
+}
+fn is_silenced(server: &ServerState, channel: &SharedChannel, user: &Su) -> bool {
+    ban::is_banned(server, channel, user, 'b') || ban::is_banned(server, channel, user, 'q')
+}
 #[allow(non_snake_case)]
 async fn handle_LIST(
     sss: &Sss,
@@ -221,7 +425,10 @@ async fn handle_LIST(
     let server = sss.lock().await;
     user.reply(RPL_LISTSTART, "Channel :Users  Name").await;
     for ch in server.get_channels() {
-        if ch.get_modes().s || !a_channels.is_empty() && !a_channels.contains(&ch.name) {
+        let modes = ch.get_modes();
+        if (modes.s || modes.p) && !ch.contains_user(user)
+            || !a_channels.is_empty() && !a_channels.contains(&ch.name)
+        {
             continue;
         }
         let topic = ch.get_topic_info().map(|(t, _, _)| t).unwrap_or_default();
@@ -229,18 +436,107 @@ async fn handle_LIST(
     }
     user.reply(RPL_LISTEND, ":End of /LIST").await;
 }
-// INVITE
-// KICK
+#[allow(non_snake_case)]
+async fn handle_INVITE(sss: &Sss, user: &Su, a_nickname: String, a_channel: String) -> Res {
+    let server = sss.lock().await;
+    if a_nickname.is_empty() {
+        for channel in server.get_channels().filter(|channel| channel.is_invited(user)) {
+            user.reply(RPL_INVITELIST, &channel.name).await;
+        }
+        user.reply(RPL_ENDOFINVITELIST, ":End of /INVITE list").await;
+        return;
+    }
+    let Some(target) = server.get_user(&a_nickname) else {
+        user.reply(ERR_NOSUCHNICK, &format!("{} :No such nick/channel", a_nickname)).await;
+        return;
+    };
+    let Some(channel) = server.get_channel(&a_channel) else {
+        user.reply(ERR_NOSUCHCHANNEL, &format!("{} :No such channel", a_channel)).await;
+        return;
+    };
+    if !channel.contains_user(user) {
+        user.reply(ERR_NOTONCHANNEL, &format!("{} :You're not on that channel", a_channel)).await;
+    } else if channel.get_modes().i && !channel.is_op(user) {
+        user.reply(ERR_CHANOPRIVSNEEDED, &format!("{} :You're not a channel operator", a_channel))
+            .await;
+    } else if channel.contains_user(&target) {
+        user.reply(
+            ERR_USERONCHANNEL,
+            &format!("{} {} :is already on channel", target.get_nickname(), a_channel),
+        )
+        .await;
+    } else {
+        channel.invite(&target);
+        user.reply(RPL_INVITING, &format!("{} {}", target.get_nickname(), channel.name)).await;
+        target
+            .send(Arc::new(Message::new(
+                Some(&user.get_fqn_string()),
+                Command::INVITE { nickname: target.get_nickname(), channel: channel.name.clone() },
+            )))
+            .await;
+    }
+}
+#[allow(non_snake_case)]
+async fn handle_KICK(
+    sss: &Sss,
+    user: &Su,
+    a_channel: String,
+    a_users: Vec<String>,
+    a_reason: String,
+) -> Res {
+    let mut server = sss.lock().await;
+    let Some(channel) = server.get_channel(&a_channel) else {
+        user.reply(ERR_NOSUCHCHANNEL, &format!("{} :No such channel", a_channel)).await;
+        return;
+    };
+    if !channel.contains_user(user) {
+        user.reply(ERR_NOTONCHANNEL, &format!("{} :You're not on that channel", a_channel)).await;
+        return;
+    }
+    if !channel.is_op(user) {
+        user.reply(ERR_CHANOPRIVSNEEDED, &format!("{} :You're not a channel operator", a_channel))
+            .await;
+        return;
+    }
+    let reason = if a_reason.is_empty() { user.get_nickname() } else { a_reason };
+    let reason: String = reason.chars().take(server.config.limits.kicklen).collect();
+    for nick in a_users {
+        match server.get_user(&nick) {
+            Some(target) if channel.contains_user(&target) => {
+                channel
+                    .broadcast(Arc::new(Message::new(
+                        Some(&user.get_fqn_string()),
+                        Command::KICK {
+                            channel: channel.name.clone(),
+                            users: vec![target.get_nickname()],
+                            reason: reason.clone(),
+                        },
+                    )))
+                    .await;
+                server.remove_user_from_channel(&target, &channel);
+            }
+            Some(_) => {
+                user.reply(
+                    ERR_USERNOTINCHANNEL,
+                    &format!("{} {} :They aren't on that channel", nick, a_channel),
+                )
+                .await
+            }
+            None => user.reply(ERR_NOSUCHNICK, &format!("{} :No such nick/channel", nick)).await,
+        }
+    }
+}
 
 /* Server Queries and Commands */
 #[allow(non_snake_case)]
-async fn handle_MOTD(_sss: &Sss, user: &Su, a_target: String) -> Res {
-    #[allow(clippy::const_is_empty)]
-    if MOTD.is_empty() {
+async fn handle_MOTD(sss: &Sss, user: &Su, a_target: String) -> Res {
+    let server = sss.lock().await;
+    let config = &server.config;
+    if config.motd.is_empty() {
         user.reply(ERR_NOMOTD, ":MOTD File is missng").await;
-    } else if a_target.is_empty() || a_target == SERVERNAME {
-        user.reply(RPL_MOTDSTART, &format!(":- {} Message of the day -", SERVERNAME)).await;
-        for line in MOTD.lines() {
+    } else if a_target.is_empty() || a_target == config.servername {
+        user.reply(RPL_MOTDSTART, &format!(":- {} Message of the day -", config.servername)).await;
+        for line in config.motd.lines() {
             user.reply(RPL_MOTD, &format!(":- {line}")).await;
         }
         user.reply(RPL_ENDOFMOTD, ":End of /MOTD command").await;
@@ -262,7 +558,7 @@ async fn handle_MODE(
     user: &Su,
     a_target: String,
     a_modestring: String,
-    _a_modeargs: Vec<String>,
+    a_modeargs: Vec<String>,
 ) -> Res {
     let server = sss.lock().await;
     if let Some(target_user) = server.get_user(&a_target) {
@@ -274,100 +570,210 @@ async fn handle_MODE(
             user.reply(RPL_UMODEIS, &format!("{} {}", a_target, modes)).await
         } else {
             // set user modes
-            let mut mode_iter = a_modestring.chars();
-            let mut rep_modestring = String::from("");
-            let mut plus_or_minus = mode_iter.next().unwrap();
+            let classes = ModeClasses { d: USERMODES, ..Default::default() };
+            let mut applied = Vec::new();
             let mut unknown = false;
-            // TODO: coalesce duplicate modes
-            for modechar in mode_iter {
-                if modechar == '+' || modechar == '-' {
-                    plus_or_minus = modechar;
-                } else if !USERMODES.contains(modechar) {
+            for change in parse_mode_changes(&a_modestring, &[], &classes, 0) {
+                if !USERMODES.contains(change.mode) {
                     unknown = true;
-                } else if match plus_or_minus {
-                    '+' => user.add_mode(modechar),
-                    _ => user.remove_mode(modechar),
+                } else if change.mode == 'Z' || change.mode == 'o' && change.set {
+                    // +o is only set by OPER, and Z only by the connection
+                } else if match change.set {
+                    true => user.add_mode(change.mode),
+                    false => user.remove_mode(change.mode),
                 } {
-                    rep_modestring.push(plus_or_minus);
-                    rep_modestring.push(modechar);
+                    applied.push(change);
                 }
             }
             if unknown {
                 user.reply(ERR_UMODEUNKNOWNFLAG, ":Unknown MODE flag").await;
             }
+            if applied.is_empty() {
+                return;
+            }
+            let (modestring, modeargs) = format_mode_changes(&applied);
             user.send(Arc::new(Message::new(
                 Some(&user.get_fqn_string()),
-                Command::MODE {
-                    target: a_target.clone(),
-                    modestring: rep_modestring,
-                    modeargs: vec![],
-                },
+                Command::MODE { target: a_target.clone(), modestring, modeargs },
             )))
             .await
         }
     } else if let Some(channel) = server.get_channel(&a_target) {
         // channel target
-        if channel.contains_user(user) {
-            if a_modestring.is_empty() {
-                // get channel modes
-                let ChannelModes { s } = channel.get_modes();
-                let mut rep_modestring = String::from("+");
-                let rep_modeargs = String::from("");
-                // type D: flags
-                [(s, 's')].iter().filter(|(b, _)| *b).for_each(|(_, c)| {
+        if a_modestring.is_empty() {
+            // get channel modes
+            let ChannelModes { k, l, i, m, n, p, s, t, .. } = channel.get_modes();
+            // as in NAMES, a secret channel does not exist for non-members
+            if s && !channel.contains_user(user) {
+                user.reply(ERR_NOSUCHCHANNEL, &format!("{} :No such channel", a_target)).await;
+                return;
+            }
+            let mut rep_modestring = String::from("+");
+            let mut rep_modeargs = String::from("");
+            // type B and C: parameters, with the key hidden from non-members
+            if let Some(key) = k {
+                rep_modestring.push('k');
+                let key = if channel.contains_user(user) { key } else { "*".to_string() };
+                rep_modeargs.push_str(&format!(" {key}"));
+            }
+            if let Some(limit) = l {
+                rep_modestring.push('l');
+                rep_modeargs.push_str(&format!(" {limit}"));
+            }
+            // type D: flags
+            [(i, 'i'), (m, 'm'), (n, 'n'), (p, 'p'), (s, 's'), (t, 't')]
+                .iter()
+                .filter(|(b, _)| *b)
+                .for_each(|(_, c)| {
                     rep_modestring.push(*c);
                 });
+            user.reply(
+                RPL_CHANNELMODEIS,
+                &format!("{} {}{}", a_target, rep_modestring, rep_modeargs),
+            )
+            .await;
+            user.reply(RPL_CREATIONTIME, &format!("{} {}", a_target, channel.creation_time)).await
+        } else {
+            let changes = parse_mode_changes(
+                &a_modestring,
+                &a_modeargs,
+                &CHANNEL_MODE_CLASSES,
+                server.config.limits.modes,
+            );
+            // list modes without a mask are queries, which need no privileges
+            let (queries, changes): (Vec<_>, Vec<_>) = changes.into_iter().partition(|change| {
+                CHANNEL_MODE_CLASSES.a.contains(change.mode) && change.param.is_none()
+            });
+            for query in queries {
+                send_list(user, &channel, query.mode).await;
+            }
+            if changes.is_empty() {
+                return;
+            }
+            if !channel.is_op(user) {
                 user.reply(
-                    RPL_CHANNELMODEIS,
-                    &format!("{} {}{}", a_target, rep_modestring, rep_modeargs),
+                    ERR_CHANOPRIVSNEEDED,
+                    &format!("{} :You're not a channel operator", a_target),
                 )
                 .await;
-                user.reply(RPL_CREATIONTIME, &format!("{} {}", a_target, channel.creation_time))
-                    .await
-            } else if let Some(invalid) =
-                a_modestring.chars().find(|&c| !matches!(c, '+' | '-') && !CHANNELMODES.contains(c))
-            {
-                // invalid set channel modes
-                user.reply(ERR_UNKNOWNMODE, &format!("{invalid} :is unknown mode char to me")).await
-            } else {
-                // set channel modes
-                let mut mode_iter = a_modestring.chars();
-                let mut rep_modestring = String::from("");
-                let rep_modeargs = Vec::new();
-                let mut plus_or_minus = mode_iter.next().unwrap();
-                // TODO: coalesce dupes
-                for modechar in mode_iter {
-                    match modechar {
-                        '+' | '-' => plus_or_minus = modechar,
-                        // type D: flags
-                        's' => {
-                            if channel.set_mode_type_d(modechar, plus_or_minus == '+') {
-                                rep_modestring.push(plus_or_minus);
-                                rep_modestring.push(modechar);
-                            }
+                return;
+            }
+            // set channel modes
+            let mut applied = Vec::new();
+            for change in changes {
+                if let Some((numeric, params)) = mode_param_error(&a_target, &change) {
+                    user.reply(numeric, &params).await;
+                    continue;
+                }
+                let ModeChange { set, mode, param } = change;
+                // Some(param to show) if the change was made
+                let shown = match (mode, param) {
+                    // prefix modes: a nick argument
+                    ('o' | 'v', Some(nick)) => match server.get_user(&nick) {
+                        Some(target) if channel.contains_user(&target) => channel
+                            .set_member_mode(&target, mode, set)
+                            .then(|| Some(target.get_nickname())),
+                        Some(_) => {
+                            user.reply(
+                                ERR_USERNOTINCHANNEL,
+                                &format!("{} {} :They aren't on that channel", nick, a_target),
+                            )
+                            .await;
+                            None
+                        }
+                        None => {
+                            user.reply(ERR_NOSUCHNICK, &format!("{} :No such nick/channel", nick))
+                                .await;
+                            None
+                        }
+                    },
+                    // type A: lists of masks
+                    ('b' | 'e' | 'I' | 'q', Some(mask)) => {
+                        let mask = if mask.starts_with('$') { mask } else { normalize_mask(&mask) };
+                        if !set {
+                            channel.remove_list_entry(mode, &mask).map(Some)
+                        } else if mask.starts_with('$') && !ban::is_valid_extban(&mask) {
+                            user.reply(
+                                ERR_INVALIDMODEPARAM,
+                                &format!("{} {} {} :Invalid extban", a_target, mode, mask),
+                            )
+                            .await;
+                            None
+                        } else if channel.list_count() >= server.config.limits.maxlist {
+                            user.reply(
+                                ERR_BANLISTFULL,
+                                &format!("{} {} :Channel list is full", a_target, mode),
+                            )
+                            .await;
+                            None
+                        } else {
+                            channel
+                                .add_list_entry(mode, &mask, &user.get_fqn_string())
+                                .then_some(Some(mask))
+                        }
+                    }
+                    // type B: a parameter when set and unset, though unsetting does not need it
+                    ('k', _) if !set => channel.set_key(None).then(|| Some("*".to_string())),
+                    ('k', Some(key)) if !is_valid_key(&key) => {
+                        user.reply(
+                            ERR_INVALIDKEY,
+                            &format!("{} :Key is not well-formed", a_target),
+                        )
+                        .await;
+                        None
+                    }
+                    ('k', Some(key)) => channel.set_key(Some(key.clone())).then_some(Some(key)),
+                    // type C: a parameter when set
+                    ('l', _) if !set => channel.set_limit(None).then_some(None),
+                    ('l', Some(limit)) => match limit.parse::<usize>() {
+                        Ok(limit) if limit > 0 => {
+                            channel.set_limit(Some(limit)).then(|| Some(limit.to_string()))
                         }
-                        _ => unreachable!(),
+                        _ => None,
+                    },
+                    // type D: flags
+                    (c, _) if CHANNEL_FLAGS.contains(c) => {
+                        channel.set_mode_type_d(mode, set).then_some(None)
                     }
+                    (c, _) => {
+                        user.reply(ERR_UNKNOWNMODE, &format!("{c} :is unknown mode char to me"))
+                            .await;
+                        None
+                    }
+                };
+                if let Some(param) = shown {
+                    applied.push(ModeChange { set, mode, param });
                 }
-
-                channel
-                    .broadcast(Arc::new(Message::new(
-                        Some(&user.get_fqn_string()),
-                        Command::MODE {
-                            target: a_target.clone(),
-                            modestring: rep_modestring,
-                            modeargs: rep_modeargs,
-                        },
-                    )))
-                    .await
             }
-        } else {
-            user.reply(
-                ERR_CHANOPRIVSNEEDED,
-                &format!("{} :You're not a channel operator", a_target),
-            )
-            .await
+            if applied.is_empty() {
+                return;
+            }
+
+            let (modestring, modeargs) = format_mode_changes(&applied);
+            channel
+                .broadcast(Arc::new(Message::new(
+                    Some(&user.get_fqn_string()),
+                    Command::MODE { target: a_target.clone(), modestring, modeargs },
+                )))
+                .await
+        }
+    }
+}
+
+/// The error for a channel mode change with a missing or invalid parameter.
+/// Unsetting +k and +l needs no parameter, and list modes without one were already
+/// answered as queries.
+fn mode_param_error(channel: &str, change: &ModeChange) -> Option<(Numeric, String)> {
+    match (change.mode, &change.param) {
+        ('k' | 'l', None) if !change.set => None,
+        (mode, None) if CHANNEL_MODE_CLASSES.contains(mode) && !CHANNEL_FLAGS.contains(mode) => {
+            Some((ERR_NEEDMOREPARAMS, String::from("MODE :Not enough parameters")))
         }
+        ('l', Some(limit)) if !limit.parse::<usize>().is_ok_and(|limit| limit > 0) => Some((
+            ERR_INVALIDMODEPARAM,
+            format!("{} l {} :Limit must be a positive number", channel, limit),
+        )),
+        _ => None,
     }
 }
 
@@ -375,83 +781,544 @@ async fn handle_MODE(
 #[allow(non_snake_case)]
 async fn handle_PRIVMSG(sss: &Sss, su: &Su, targets: Vec<String>, text: String) -> Res {
     let server = sss.lock().await;
-    let nick = su.get_nickname();
-
-    for target in targets {
-        let success_msg = Arc::new(Message::new(
-            Some(&nick),
+    su.mark_active();
+    for (index, target) in targets.into_iter().enumerate() {
+        if index >= server.config.limits.maxtargets {
+            su.reply(ERR_TOOMANYTARGETS, &format!("{} :Too many targets", target)).await;
+            continue;
+        }
+        let message = Arc::new(Message::new(
+            Some(&su.get_fqn_string()),
             Command::PRIVMSG { targets: vec![target.clone()], text: text.to_owned() },
         ));
-        if let Some(channel) = server.get_channel(&target) {
-            for user in channel.get_users() {
-                let user_lock = user;
-                if user_lock.get_nickname() != *nick {
-                    user_lock.send(Arc::clone(&success_msg)).await;
+        match deliver(&server, su, &target, message).await {
+            Ok(()) => {
+                let away = server.get_user(&target).and_then(|user| {
+                    user.get_away().map(|text| format!("{} :{}", user.get_nickname(), text))
+                });
+                if let Some(away) = away {
+                    su.reply(RPL_AWAY, &away).await;
                 }
             }
-        } else if let Some(user) = server.get_user(&target) {
-            user.send(Arc::clone(&success_msg)).await;
-        } else {
-            su.reply(ERR_NOSUCHNICK, ":No such nick/channel").await;
+            Err((numeric, params)) => su.reply(numeric, &params).await,
+        }
+    }
+}
+/// Like PRIVMSG, but undeliverable notices are dropped without a reply.
+#[allow(non_snake_case)]
+async fn handle_NOTICE(sss: &Sss, su: &Su, targets: Vec<String>, text: String) -> Res {
+    let server = sss.lock().await;
+    su.mark_active();
+    for target in targets.into_iter().take(server.config.limits.maxtargets) {
+        let message = Arc::new(Message::new(
+            Some(&su.get_fqn_string()),
+            Command::NOTICE { targets: vec![target.clone()], text: text.to_owned() },
+        ));
+        let _ = deliver(&server, su, &target, message).await;
+    }
+}
+/// Sends a PRIVMSG or NOTICE to a user, a channel, or the ops or voiced members of a channel
+/// (STATUSMSG, e.g. `@#chan`). The error is for PRIVMSG to reply with.
+async fn deliver(
+    server: &ServerState,
+    su: &Su,
+    target: &str,
+    message: Arc<Message>,
+) -> Result<(), (Numeric, String)> {
+    let no_such_nick = || (ERR_NOSUCHNICK, format!("{} :No such nick/channel", target));
+    let status = target.chars().next().filter(|c| STATUSMSG.contains(*c));
+    let channel_name = &target[status.map_or(0, char::len_utf8)..];
+    if !channel_name.starts_with(|c| CHANTYPES.contains(c)) {
+        if status.is_some() {
+            return Err(no_such_nick());
+        }
+        let user = server.get_user(target).ok_or_else(no_such_nick)?;
+        user.send(message).await;
+        return Ok(());
+    }
+    let channel = server.get_channel(channel_name).ok_or_else(no_such_nick)?;
+    if !can_send(server, &channel, su) {
+        return Err((ERR_CANNOTSENDTOCHAN, format!("{} :Cannot send to channel", channel.name)));
+    }
+    for (user, membership) in channel.get_members() {
+        let addressed = match status {
+            Some('@') => membership.op,
+            Some(_) => membership.op || membership.voice,
+            None => true,
+        };
+        if addressed && !User::are_same(&user, su) {
+            user.send(Arc::clone(&message)).await;
         }
     }
+    Ok(())
 }
-// NOTICE
 
 /* User Based Queries */
 #[allow(non_snake_case)]
-async fn handle_WHO(sss: &Sss, su: &Su, mask: String) -> Res {
+async fn handle_WHO(
+    sss: &Sss,
+    su: &Su,
+    a_mask: String,
+    a_opers_only: bool,
+    a_fields: Option<String>,
+    a_token: String,
+) -> Res {
     let server = sss.lock().await;
-
-    if mask.starts_with("#") {
-        // todo: other prefixes
-        if let Some(channel) = server.get_channel(&mask) {
-            for masked_user in channel.get_users() {
-                let reply = {
-                    let u = masked_user;
-                    format!(
-                        "{} {} {} {} {} H :0 {}",
-                        mask,
-                        u.username,
-                        u.hostname,
-                        SERVERNAME,
-                        u.get_nickname(),
-                        u.realname
-                    )
-                };
-                su.reply(RPL_WHOREPLY, &reply).await;
+    // each user with the channel and status to show
+    let mut found: Vec<(Su, Option<(String, Membership)>)> = Vec::new();
+    if let Some(channel) = server.get_channel(&a_mask) {
+        let is_member = channel.contains_user(su);
+        let modes = channel.get_modes();
+        if is_member || !(modes.s || modes.p) {
+            for (user, membership) in channel.get_members() {
+                if is_member || !user.is_invisible() {
+                    found.push((user, Some((channel.name.clone(), membership))));
+                }
             }
         }
     } else {
-        // todo: user masks
-        if let Some(masked_user) = server.get_user(&mask) {
-            let reply = {
-                let u = masked_user;
-                format!(
-                    "* {} {} {} {} H :0 {}",
-                    u.username,
-                    u.hostname,
-                    SERVERNAME,
-                    u.get_nickname(),
-                    u.realname
-                )
-            };
-            su.reply(RPL_WHOREPLY, &reply).await;
+        let everyone = matches!(a_mask.as_str(), "" | "0" | "*");
+        for user in server.users() {
+            let matches = everyone
+                || [
+                    user.get_nickname().as_str(),
+                    &user.username,
+                    &user.hostname,
+                    &user.realname,
+                    &user.servername,
+                ]
+                .iter()
+                .any(|field| wildcard_match(&a_mask, field));
+            if matches && can_see(su, &user) {
+                found.push((user, None));
+            }
+        }
+    }
+    send_who_replies(su, found, a_opers_only, a_fields.as_deref(), &a_token).await;
+    su.reply(RPL_ENDOFWHO, &format!("{} :End of WHO list", a_mask)).await;
+}
+/// Invisible users are only seen by users sharing a channel with them, and by opers.
+fn can_see(su: &Su, user: &Su) -> bool {
+    !user.is_invisible()
+        || User::are_same(su, user)
+        || su.is_oper()
+        || user.get_channels().any(|channel| channel.contains_user(su))
+}
+/// Sends RPL_WHOREPLY, or RPL_WHOSPCRPL with the WHOX `fields` in their fixed order.
+async fn send_who_replies(
+    su: &Su,
+    found: Vec<(Su, Option<(String, Membership)>)>,
+    opers_only: bool,
+    fields: Option<&str>,
+    token: &str,
+) {
+    for (user, channel) in found {
+        if opers_only && !user.is_oper() {
+            continue;
+        }
+        let (channel_name, prefix) = match channel {
+            Some((name, membership)) => (name, membership.prefix()),
+            None => (String::from("*"), ""),
+        };
+        let oper = if user.is_oper() { "*" } else { "" };
+        let flags = format!("{}{}{}", user.here_or_gone(), oper, prefix);
+        let Some(fields) = fields else {
+            su.reply(
+                RPL_WHOREPLY,
+                &format!(
+                    "{} {} {} {} {} {} :0 {}",
+                    channel_name,
+                    user.username,
+                    user.hostname,
+                    user.servername,
+                    user.get_nickname(),
+                    flags,
+                    user.realname
+                ),
+            )
+            .await;
+            continue;
+        };
+        let reply: Vec<String> = "tcuihsnfdlaor"
+            .chars()
+            .filter(|&field| fields.contains(field))
+            .map(|field| match field {
+                't' => token.to_string(),
+                'c' => channel_name.clone(),
+                'u' => user.username.clone(),
+                'i' => match user.ip {
+                    // a leading ':' would start a trailing parameter, as with hostnames
+                    Some(ip) if ip.to_string().starts_with(':') => format!("0{ip}"),
+                    Some(ip) => ip.to_string(),
+                    None => String::from("255.255.255.255"),
+                },
+                'h' => user.hostname.clone(),
+                's' => user.servername.clone(),
+                'n' => user.get_nickname(),
+                'f' => flags.clone(),
+                'd' => String::from("0"),
+                'l' => user.idle_secs().to_string(),
+                'a' => user.account().unwrap_or_else(|| String::from("0")),
+                'o' => String::from("n/a"),
+                // 'r' comes last, so it can be the trailing parameter
+                _ => format!(":{}", user.realname),
+            })
+            .collect();
+        su.reply(RPL_WHOSPCRPL, &reply.join(" ")).await;
+    }
+}
+#[allow(non_snake_case)]
+async fn handle_WHOIS(sss: &Sss, su: &Su, a_target: String, a_nicks: Vec<String>) -> Res {
+    let server = sss.lock().await;
+    // "WHOIS nick nick" asks the server the user is on, which is always this one
+    if !a_target.is_empty()
+        && !a_target.eq_ignore_ascii_case(&su.servername)
+        && server.get_user(&a_target).is_none()
+    {
+        su.reply(ERR_NOSUCHSERVER, &format!("{} :No such server", a_target)).await;
+        return;
+    }
+    for nick in &a_nicks {
+        let Some(user) = server.get_user(nick) else {
+            su.reply(ERR_NOSUCHNICK, &format!("{} :No such nick/channel", nick)).await;
+            continue;
+        };
+        let nick = user.get_nickname();
+        su.reply(
+            RPL_WHOISUSER,
+            &format!("{} {} {} * :{}", nick, user.username, user.hostname, user.realname),
+        )
+        .await;
+        // secret and private channels are only shown to their members
+        let channels: Vec<String> = user
+            .get_channels()
+            .filter(|channel| {
+                let modes = channel.get_modes();
+                !(modes.s || modes.p) || channel.contains_user(su)
+            })
+            .map(|channel| {
+                let prefix = channel.get_membership(&user).unwrap_or_default().prefix();
+                format!("{}{}", prefix, channel.name)
+            })
+            .collect();
+        su.reply_list(RPL_WHOISCHANNELS, &format!("{} :", nick), &channels, "", usize::MAX).await;
+        su.reply(
+            RPL_WHOISSERVER,
+            &format!("{} {} :{} IRC server", nick, user.servername, server.config.network),
+        )
+        .await;
+        if let Some(text) = user.get_away() {
+            su.reply(RPL_AWAY, &format!("{} :{}", nick, text)).await;
+        }
+        if user.is_oper() {
+            su.reply(RPL_WHOISOPERATOR, &format!("{} :is an IRC operator", nick)).await;
+        }
+        if user.get_modes().any(|mode| mode == 'Z') {
+            su.reply(RPL_WHOISSECURE, &format!("{} :is using a secure connection", nick)).await;
         }
+        // not sent until accounts exist, see User::account
+        if let Some(account) = user.account() {
+            su.reply(RPL_WHOISACCOUNT, &format!("{} {} :is logged in as", nick, account)).await;
+        }
+        su.reply(
+            RPL_WHOISIDLE,
+            &format!("{} {} {} :seconds idle, signon time", nick, user.idle_secs(), user.signon),
+        )
+        .await;
+    }
+    su.reply(RPL_ENDOFWHOIS, &format!("{} :End of /WHOIS list", a_nicks.join(","))).await;
+}
+#[allow(non_snake_case)]
+async fn handle_WHOWAS(sss: &Sss, su: &Su, a_nick: String, a_count: Option<usize>) -> Res {
+    let server = sss.lock().await;
+    let mut found = false;
+    for entry in server.whowas().find(&a_nick).take(a_count.unwrap_or(usize::MAX)) {
+        found = true;
+        su.reply(
+            RPL_WHOWASUSER,
+            &format!("{} {} {} * :{}", entry.nick, entry.username, entry.hostname, entry.realname),
+        )
+        .await;
+        su.reply(
+            RPL_WHOISSERVER,
+            &format!(
+                "{} {} :{}",
+                entry.nick,
+                entry.servername,
+                entry.signoff.format("%a %b %d %Y at %T UTC")
+            ),
+        )
+        .await;
     }
-    su.reply(RPL_ENDOFWHO, &format!("{} :End of WHO list", mask)).await;
+    if !found {
+        su.reply(ERR_WASNOSUCHNICK, &format!("{} :There was no such nickname", a_nick)).await;
+    }
+    su.reply(RPL_ENDOFWHOWAS, &format!("{} :End of WHOWAS", a_nick)).await;
 }
-// WHOIS
-// WHOWAS
 
 /* Operator Messages */
 // KILL
-// REHASH
+#[allow(non_snake_case)]
+async fn handle_REHASH(sss: &Sss, su: &Su) -> Res {
+    let mut server = sss.lock().await;
+    if !su.is_oper() {
+        su.reply(ERR_NOPRIVILEGES, ":Permission Denied- You're not an IRC operator").await;
+        return;
+    }
+    let path = server.config_path().map(|p| p.display().to_string()).unwrap_or_default();
+    su.reply(RPL_REHASHING, &format!("{} :Rehashing", path)).await;
+    match server.rehash(sss) {
+        Ok(not_applied) => {
+            for change in not_applied {
+                su.notice(&format!("*** Rehash: {}", change)).await;
+            }
+        }
+        Err(e) => su.notice(&format!("*** Rehash failed: {}", e)).await,
+    }
+}
 // RESTART
 // SQUIT
 
 /* Optional Messages */
-// AWAY
+#[allow(non_snake_case)]
+async fn handle_AWAY(sss: &Sss, su: &Su, a_text: String) -> Res {
+    let awaylen = sss.lock().await.config.limits.awaylen;
+    if a_text.is_empty() {
+        su.set_away(None);
+        su.reply(RPL_UNAWAY, ":You are no longer marked as being away").await
+    } else {
+        su.set_away(Some(a_text.chars().take(awaylen).collect()));
+        su.reply(RPL_NOWAWAY, ":You have been marked as being away").await
+    }
+}
+#[allow(non_snake_case)]
+async fn handle_ISON(sss: &Sss, su: &Su, a_nicknames: Vec<String>) -> Res {
+    let server = sss.lock().await;
+    let online: Vec<String> = a_nicknames
+        .iter()
+        .filter_map(|nick| server.get_user(nick))
+        .map(|user| user.get_nickname())
+        .collect();
+    su.reply(RPL_ISON, &format!(":{}", online.join(" "))).await
+}
 // LINKS
-// USERHOST
+#[allow(non_snake_case)]
+async fn handle_USERHOST(sss: &Sss, su: &Su, a_nicknames: Vec<String>) -> Res {
+    let server = sss.lock().await;
+    // nick[*]=<+|->user@host, with * for opers and - for away users
+    let replies: Vec<String> = a_nicknames
+        .iter()
+        .filter_map(|nick| server.get_user(nick))
+        .map(|user| {
+            format!(
+                "{}{}={}{}@{}",
+                user.get_nickname(),
+                if user.is_oper() { "*" } else { "" },
+                if user.get_away().is_some() { '-' } else { '+' },
+                user.username,
+                user.hostname
+            )
+        })
+        .collect();
+    su.reply(RPL_USERHOST, &format!(":{}", replies.join(" "))).await
+}
 // WALLOPS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, BufReader, DuplexStream};
+
+    use crate::config::Config;
+
+    fn register(server: &mut ServerState, nick: &str) -> (SharedUser, DuplexStream) {
+        let (user, output) = User::for_test(nick);
+        server.try_update_unregistered_nick("", nick);
+        (server.register_user(user), output)
+    }
+
+    #[tokio::test]
+    async fn test_channelless_nicks() {
+        let mut server = ServerState::new(Config::default(), None);
+        let (requester, _) = register(&mut server, "requester");
+        let (public, _) = register(&mut server, "public");
+        let (secret, _) = register(&mut server, "secret");
+        let _alone = register(&mut server, "alone");
+        let (invisible, _) = register(&mut server, "invisible");
+        invisible.add_mode('i');
+        let channel = server.create_channel("#public");
+        server.add_user_to_channel(&public, &channel);
+        let channel = server.create_channel("#secret");
+        channel.set_mode_type_d('s', true);
+        server.add_user_to_channel(&secret, &channel);
+
+        let mut nicks = channelless_nicks(&server, &requester);
+        nicks.sort();
+        assert_eq!(nicks, ["alone", "requester", "secret"]);
+        // members of the secret channel see it, so its users are listed there instead
+        server.add_user_to_channel(&requester, &channel);
+        assert_eq!(channelless_nicks(&server, &requester), ["alone"]);
+    }
+
+    #[tokio::test]
+    async fn test_kick_missing_target() {
+        let mut server = ServerState::new(Config::default(), None);
+        let (alice, output) = register(&mut server, "alice");
+        let (bob, _) = register(&mut server, "bob");
+        let channel = server.create_channel("#c");
+        server.add_user_to_channel(&alice, &channel);
+        server.add_user_to_channel(&bob, &channel);
+        channel.set_member_mode(&alice, 'o', true);
+        let sss = Arc::new(tokio::sync::Mutex::new(server));
+
+        let users = vec!["ghost".to_string(), "bob".to_string()];
+        handle_KICK(&sss, &alice, "#c".into(), users, "bye".into()).await;
+        let mut lines = BufReader::new(output).lines();
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            ":srv 401 alice ghost :No such nick/channel"
+        );
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), ":alice!@host KICK #c bob :bye");
+        assert!(!channel.contains_user(&bob));
+    }
+
+    #[tokio::test]
+    async fn test_secret_ban_list() {
+        let mut server = ServerState::new(Config::default(), None);
+        let (member, member_output) = register(&mut server, "member");
+        let (outsider, outsider_output) = register(&mut server, "outsider");
+        let channel = server.create_channel("#c");
+        server.add_user_to_channel(&member, &channel);
+        channel.add_list_entry('b', "*!*@spam", "member");
+        channel.set_mode_type_d('s', true);
+
+        send_list(&outsider, &channel, 'b').await;
+        send_list(&member, &channel, 'b').await;
+        let mut outsider_lines = BufReader::new(outsider_output).lines();
+        let line = outsider_lines.next_line().await.unwrap().unwrap();
+        assert!(line.starts_with(":srv 368 outsider #c "), "{line}");
+        let mut member_lines = BufReader::new(member_output).lines();
+        let line = member_lines.next_line().await.unwrap().unwrap();
+        assert!(line.starts_with(":srv 367 member #c *!*@spam member "), "{line}");
+    }
+
+    #[tokio::test]
+    async fn test_whox_ip() {
+        let (requester, output) = User::for_test("requester");
+        let requester = requester.register_to_arc();
+        let mut found = Vec::new();
+        for ip in [Some("10.0.0.1"), Some("::1"), None] {
+            let (mut user, _) = User::for_test("user");
+            user.ip = ip.map(|ip| ip.parse().unwrap());
+            found.push((user.register_to_arc(), None));
+        }
+        send_who_replies(&requester, found, false, Some("ih"), "0").await;
+        let mut lines = BufReader::new(output).lines();
+        for ip in ["10.0.0.1", "0::1", "255.255.255.255"] {
+            let line = lines.next_line().await.unwrap().unwrap();
+            assert_eq!(line, format!(":srv 354 requester {ip} host"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deliver_to_nick() {
+        let mut server = ServerState::new(Config::default(), None);
+        let (sender, _) = register(&mut server, "sender");
+        let (_erin, output) = register(&mut server, "erin");
+        // a target without a CHANTYPES prefix is a nick, whatever channels exist
+        server.create_channel("erin");
+
+        let message = Arc::new(Message::new(
+            Some(&sender.get_fqn_string()),
+            Command::PRIVMSG { targets: vec!["erin".into()], text: "hi".into() },
+        ));
+        assert!(deliver(&server, &sender, "erin", message).await.is_ok());
+        let line = BufReader::new(output).lines().next_line().await.unwrap().unwrap();
+        assert_eq!(line, ":sender!@host PRIVMSG erin :hi");
+        let message =
+            Arc::new(Message::new(None, Command::NOTICE { targets: vec![], text: "".into() }));
+        assert_eq!(
+            deliver(&server, &sender, "@erin", message).await.map_err(|e| e.0),
+            Err(ERR_NOSUCHNICK)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_join_invalid_names() {
+        let mut server = ServerState::new(Config::default(), None);
+        let (alice, output) = register(&mut server, "alice");
+        let sss = Arc::new(tokio::sync::Mutex::new(server));
+
+        // "JOIN ,#x" and "JOIN foo"
+        let channels = vec!["".to_string(), "#x".to_string(), "foo".to_string()];
+        handle_JOIN(&sss, &alice, channels, vec![], false).await;
+        let mut lines = BufReader::new(output).lines();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), ":srv 403 alice  :No such channel");
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), ":alice!@host JOIN #x");
+        for _ in 0..3 {
+            // RPL_NOTOPIC, RPL_NAMREPLY and RPL_ENDOFNAMES
+            lines.next_line().await.unwrap();
+        }
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            ":srv 403 alice foo :No such channel"
+        );
+        let server = sss.lock().await;
+        assert!(server.get_channel("").is_none() && server.get_channel("foo").is_none());
+    }
+
+    #[test]
+    fn test_is_valid_channel_name() {
+        for valid in ["#", "#chan", "&local", "#ü"] {
+            assert!(is_valid_channel_name(valid), "{valid}");
+        }
+        for invalid in ["", "chan", "+chan", "#a b", "#a,b", "#a:b", "#a\x07"] {
+            assert!(!is_valid_channel_name(invalid), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_secret_channel_modes() {
+        let mut server = ServerState::new(Config::default(), None);
+        let (member, member_output) = register(&mut server, "member");
+        let (outsider, outsider_output) = register(&mut server, "outsider");
+        let channel = server.create_channel("#sec");
+        server.add_user_to_channel(&member, &channel);
+        channel.set_mode_type_d('s', true);
+        let sss = Arc::new(tokio::sync::Mutex::new(server));
+
+        handle_MODE(&sss, &outsider, "#sec".into(), String::new(), vec![]).await;
+        handle_MODE(&sss, &member, "#sec".into(), String::new(), vec![]).await;
+        let line = BufReader::new(outsider_output).lines().next_line().await.unwrap().unwrap();
+        assert_eq!(line, ":srv 403 outsider #sec :No such channel");
+        let line = BufReader::new(member_output).lines().next_line().await.unwrap().unwrap();
+        assert!(line.starts_with(":srv 324 member #sec +"), "{line}");
+    }
+
+    #[test]
+    fn test_mode_param_error_missing() {
+        for mode in ['k', 'l', 'o', 'v'] {
+            let error = mode_param_error("#c", &ModeChange::new(true, mode, None));
+            assert_eq!(error.map(|(numeric, _)| numeric), Some(ERR_NEEDMOREPARAMS), "+{mode}");
+        }
+        assert!(mode_param_error("#c", &ModeChange::new(false, 'o', None)).is_some());
+        assert!(mode_param_error("#c", &ModeChange::new(false, 'k', None)).is_none());
+        assert!(mode_param_error("#c", &ModeChange::new(false, 'l', None)).is_none());
+        assert!(mode_param_error("#c", &ModeChange::new(true, 'n', None)).is_none());
+    }
+
+    #[test]
+    fn test_mode_param_error_invalid() {
+        for limit in ["foo", "0", "-1"] {
+            assert_eq!(
+                mode_param_error("#c", &ModeChange::new(true, 'l', Some(limit))),
+                Some((
+                    ERR_INVALIDMODEPARAM,
+                    format!("#c l {} :Limit must be a positive number", limit)
+                ))
+            );
+        }
+        assert!(mode_param_error("#c", &ModeChange::new(true, 'l', Some("10"))).is_none());
+        assert!(mode_param_error("#c", &ModeChange::new(true, 'o', Some("nick"))).is_none());
+    }
+}
@@ -0,0 +1,135 @@
+// HAProxy PROXY protocol, versions 1 and 2.
+// https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const V1_MAX_LEN: usize = 107;
+
+/// Reads a PROXY header from the start of the stream, returning the client address it gives.
+/// Returns None if the header does not carry an address (v1 UNKNOWN or v2 LOCAL).
+pub async fn read_header(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<SocketAddr>> {
+    // 12 bytes is shorter than any v1 header, so this never reads past the header.
+    let mut start = [0; 12];
+    stream.read_exact(&mut start).await?;
+    if start == V2_SIGNATURE {
+        let mut rest = [0; 4];
+        stream.read_exact(&mut rest).await?;
+        let len = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+        let mut addresses = vec![0; len];
+        stream.read_exact(&mut addresses).await?;
+        parse_v2(rest[0], rest[1], &addresses)
+    } else if start.starts_with(b"PROXY ") {
+        let mut line = start.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LEN {
+                return Err(invalid("PROXY v1 header is too long"));
+            }
+            line.push(stream.read_u8().await?);
+        }
+        parse_v1(std::str::from_utf8(&line).map_err(|_| invalid("PROXY v1 header is not ASCII"))?)
+    } else {
+        Err(invalid("missing PROXY header"))
+    }
+}
+
+/// "PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n"
+fn parse_v1(line: &str) -> io::Result<Option<SocketAddr>> {
+    let fields: Vec<&str> = line.trim_end_matches("\r\n").split(' ').collect();
+    match fields[..] {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", src, _dst, sport, _dport] => {
+            let ip: IpAddr = src.parse().map_err(|_| invalid("bad PROXY v1 source address"))?;
+            let port: u16 = sport.parse().map_err(|_| invalid("bad PROXY v1 source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("malformed PROXY v1 header")),
+    }
+}
+
+fn parse_v2(ver_cmd: u8, family: u8, addresses: &[u8]) -> io::Result<Option<SocketAddr>> {
+    if ver_cmd >> 4 != 2 {
+        return Err(invalid("unsupported PROXY v2 version"));
+    }
+    match ver_cmd & 0xF {
+        // LOCAL: health checks from the proxy itself
+        0 => Ok(None),
+        // PROXY
+        1 => match family >> 4 {
+            1 if addresses.len() >= 12 => {
+                let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addresses[0..4]).unwrap());
+                let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+                Ok(Some(SocketAddr::new(ip.into(), port)))
+            }
+            2 if addresses.len() >= 36 => {
+                let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addresses[0..16]).unwrap());
+                let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+                Ok(Some(SocketAddr::new(ip.into(), port)))
+            }
+            1 | 2 => Err(invalid("PROXY v2 address block is too short")),
+            // AF_UNSPEC, AF_UNIX
+            _ => Ok(None),
+        },
+        _ => Err(invalid("unsupported PROXY v2 command")),
+    }
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v1() {
+        assert_eq!(
+            parse_v1("PROXY TCP4 192.168.0.1 192.168.0.11 56324 6667\r\n").unwrap(),
+            Some("192.168.0.1:56324".parse().unwrap())
+        );
+        assert_eq!(
+            parse_v1("PROXY TCP6 2001:db8::1 2001:db8::2 56324 6667\r\n").unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+        assert_eq!(parse_v1("PROXY UNKNOWN\r\n").unwrap(), None);
+        assert!(parse_v1("PROXY TCP4 192.168.0.1\r\n").is_err());
+        assert!(parse_v1("PROXY TCP4 nope 192.168.0.11 56324 6667\r\n").is_err());
+    }
+
+    #[test]
+    fn test_v2() {
+        let tcp4 = [192, 168, 0, 1, 192, 168, 0, 11, 0xDC, 0x04, 0x1A, 0x0B];
+        assert_eq!(
+            parse_v2(0x21, 0x11, &tcp4).unwrap(),
+            Some("192.168.0.1:56324".parse().unwrap())
+        );
+        assert_eq!(parse_v2(0x20, 0x00, &[]).unwrap(), None);
+        assert!(parse_v2(0x21, 0x11, &tcp4[..8]).is_err());
+        assert!(parse_v2(0x11, 0x11, &tcp4).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_header() {
+        let mut stream: &[u8] = b"PROXY TCP4 10.0.0.1 10.0.0.2 1234 6667\r\nNICK aki\r\n";
+        let addr = read_header(&mut stream).await.unwrap();
+        assert_eq!(addr, Some("10.0.0.1:1234".parse().unwrap()));
+        assert_eq!(stream, b"NICK aki\r\n");
+
+        let mut v2 = V2_SIGNATURE.to_vec();
+        v2.extend([0x21, 0x11, 0, 12, 10, 0, 0, 1, 10, 0, 0, 2, 0x04, 0xD2, 0x1A, 0x0B]);
+        v2.extend(b"NICK aki\r\n");
+        let mut stream = &v2[..];
+        let addr = read_header(&mut stream).await.unwrap();
+        assert_eq!(addr, Some("10.0.0.1:1234".parse().unwrap()));
+        assert_eq!(stream, b"NICK aki\r\n");
+
+        let mut stream: &[u8] = b"NICK aki\r\nUSER aki 0 * :aki\r\n";
+        assert!(read_header(&mut stream).await.is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use common::message::Message;
+use owo_colors::OwoColorize;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, watch},
+    task,
+};
+
+/// Outgoing messages of one connection. They are written by a separate task,
+/// so that senders never wait on a slow client.
+/// Once more than `limit` bytes are waiting to be written, the queue is closed.
+pub struct SendQueue {
+    tx: mpsc::UnboundedSender<Arc<Message>>,
+    queued_bytes: Arc<AtomicUsize>,
+    limit: usize,
+    /// Set to a reason when the server closes the connection.
+    close_reason: watch::Sender<Option<String>>,
+}
+
+impl SendQueue {
+    /// Spawns the task that writes queued messages to `writer`.
+    /// The task stops once the queue is closed or dropped.
+    pub fn spawn(mut writer: impl AsyncWrite + Unpin + Send + 'static, limit: usize) -> SendQueue {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Arc<Message>>();
+        let queued_bytes = Arc::new(AtomicUsize::new(0));
+        let (close_reason, mut closed) = watch::channel(None);
+        let queued = Arc::clone(&queued_bytes);
+
+        task::spawn(async move {
+            loop {
+                // A closed queue is abandoned, but a dropped one is drained first.
+                let msg = tokio::select! {
+                    biased;
+                    Ok(_) = closed.wait_for(Option::is_some) => break,
+                    msg = rx.recv() => match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    },
+                };
+                println!("{}", format!("-> {msg}").truecolor(100, 110, 135));
+                let line = msg.to_line();
+                tokio::select! {
+                    biased;
+                    Ok(_) = closed.wait_for(Option::is_some) => break,
+                    result = writer.write_all(line.as_bytes()) => if let Err(e) = result {
+                        eprintln!("Write error: {}", e);
+                        break;
+                    },
+                }
+                queued.fetch_sub(line.len(), Ordering::Relaxed);
+            }
+        });
+
+        SendQueue { tx, queued_bytes, limit, close_reason }
+    }
+
+    /// Queues a message without waiting. Closes the queue with "SendQ exceeded" if over the limit.
+    pub fn push(&self, message: Arc<Message>) {
+        if self.is_closed() {
+            return;
+        }
+        let len = message.to_line().len();
+        if self.queued_bytes.fetch_add(len, Ordering::Relaxed) + len > self.limit {
+            self.close("SendQ exceeded");
+        } else {
+            let _ = self.tx.send(message);
+        }
+    }
+
+    /// Stops writing, dropping anything still queued. Only the first reason is kept.
+    pub fn close(&self, reason: &str) {
+        self.close_reason.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(reason.to_owned());
+            true
+        });
+    }
+    pub fn is_closed(&self) -> bool {
+        self.close_reason.borrow().is_some()
+    }
+    /// The receiver holds the reason once the queue is closed.
+    pub fn subscribe_close(&self) -> watch::Receiver<Option<String>> {
+        self.close_reason.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use common::message::Command;
+    use tokio::{
+        io::{AsyncReadExt, duplex},
+        time::timeout,
+    };
+
+    use super::*;
+
+    fn ping(token: &str) -> Arc<Message> {
+        Arc::new(Message::new(None, Command::PING { token: token.to_owned() }))
+    }
+
+    #[tokio::test]
+    async fn test_limit() {
+        let len = ping("token").to_line().len();
+        // nobody reads, so no line is ever fully written
+        let (writer, _reader) = duplex(1);
+        let queue = SendQueue::spawn(writer, len * 3);
+        let closed = queue.subscribe_close();
+        for _ in 0..3 {
+            queue.push(ping("token"));
+        }
+        assert!(!queue.is_closed());
+        queue.push(ping("token"));
+        assert!(queue.is_closed());
+        assert_eq!(closed.borrow().as_deref(), Some("SendQ exceeded"));
+    }
+
+    #[tokio::test]
+    async fn test_written_bytes_are_released() {
+        let line = ping("token").to_line();
+        let (writer, mut reader) = duplex(1024);
+        let queue = SendQueue::spawn(writer, line.len());
+        queue.push(ping("token"));
+        let mut buf = vec![0; line.len()];
+        reader.read_exact(&mut buf).await.unwrap();
+        timeout(Duration::from_secs(1), async {
+            while queue.queued_bytes.load(Ordering::Relaxed) != 0 {
+                task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        // would exceed the limit if the first line were still counted
+        queue.push(ping("token"));
+        assert!(!queue.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_drained_on_drop() {
+        let (writer, mut reader) = duplex(1024);
+        let queue = SendQueue::spawn(writer, 1024);
+        for token in ["a", "b", "c"] {
+            queue.push(ping(token));
+        }
+        drop(queue);
+        let mut output = String::new();
+        reader.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "PING :a\r\nPING :b\r\nPING :c\r\n");
+    }
+}
@@ -1,6 +1,7 @@
 use std::{
     collections::{HashMap, HashSet},
     fmt::{Debug, Display},
+    path::PathBuf,
     sync::Arc,
 };
 
@@ -9,29 +10,85 @@ use tokio::sync::Mutex; // todo: avoid tokio Mutex?
 
 use crate::{
     channel::{Channel, SharedChannel},
+    config::{Config, ConfigError},
+    listener::Listeners,
     user::{SharedUser, User},
+    whowas::{WhowasEntry, WhowasHistory},
 };
 
 pub struct ServerState {
+    pub config: Config,
+    /// File the config was loaded from, if any. Used by REHASH.
+    config_path: Option<PathBuf>,
+    listeners: Listeners,
     pub creation_datetime: String,
     users: HashMap<String, SharedUser>,       // key=nick
     channels: HashMap<String, SharedChannel>, // key=name
     unregistered_nicks: HashSet<String>,
+    whowas: WhowasHistory,
 }
 pub type SharedServerState = Arc<Mutex<ServerState>>;
 
 // functions panic if a SharedUser that requires locking is already locked.
 impl ServerState {
-    pub fn new() -> Self {
+    pub fn new(config: Config, config_path: Option<PathBuf>) -> Self {
         ServerState {
+            config,
+            config_path,
+            listeners: Listeners::default(),
             // Day Mon Date Year at Time UTC
             creation_datetime: chrono::Utc::now().format("%a %b %d %Y at %T UTC").to_string(),
             users: HashMap::new(),
             channels: HashMap::new(),
             unregistered_nicks: HashSet::new(),
+            whowas: WhowasHistory::default(),
         }
     }
 
+    /// Starts, stops and updates listeners to match the current config.
+    /// `sss` must be the SharedServerState containing self, for the listeners to hand connections to.
+    /// Returns a description of each listener that could not be started or updated.
+    pub fn update_listeners(&mut self, sss: &SharedServerState) -> Vec<String> {
+        self.listeners.update(sss, &self.config)
+    }
+
+    /// Reloads the config file, applying every setting that can be changed while running.
+    /// Returns a description of each change that could not be applied.
+    pub fn rehash(&mut self, sss: &SharedServerState) -> Result<Vec<String>, ConfigError> {
+        let path = self.config_path.as_ref().ok_or(ConfigError::NoPath)?;
+        let mut new_config = Config::load(path)?;
+        let mut not_applied = Vec::new();
+
+        if new_config.servername != self.config.servername {
+            not_applied.push(format!(
+                "servername changed to {}, restart the server to apply",
+                new_config.servername
+            ));
+            new_config.servername = self.config.servername.clone();
+        }
+        // These are read when a client connects or registers, so existing connections keep the old values.
+        if new_config.timeouts != self.config.timeouts {
+            not_applied.push("timeouts changed, they apply to new connections only".to_string());
+        }
+        if new_config.classes != self.config.classes {
+            not_applied.push(
+                "connection classes changed, SendQ and flood limits apply to new connections only"
+                    .to_string(),
+            );
+        }
+        if new_config.bans != self.config.bans {
+            not_applied.push(
+                "server bans changed, they are not checked against connected users".to_string(),
+            );
+        }
+        self.config = new_config;
+        not_applied.extend(self.update_listeners(sss));
+        Ok(not_applied)
+    }
+    pub fn config_path(&self) -> Option<&PathBuf> {
+        self.config_path.as_ref()
+    }
+
     pub fn contains_nick(&self, nick: &str) -> bool {
         self.users.contains_key(nick)
     }
@@ -49,6 +106,7 @@ impl ServerState {
         if self.contains_nick(new_nick) || self.unregistered_nicks.contains(new_nick) {
             return false;
         }
+        self.whowas.push(WhowasEntry::new(user), self.config.whowas_size);
         let user2 = self.users.remove(&user.get_nickname()).unwrap();
         user2.set_nickname(new_nick);
         self.users.insert(new_nick.to_owned(), user2);
@@ -81,12 +139,21 @@ impl ServerState {
     /// Only use in main.rs
     pub fn remove_user(&mut self, user: SharedUser) {
         let nick = user.get_nickname();
+        self.whowas.push(WhowasEntry::new(&user), self.config.whowas_size);
         for channel in user.get_channels() {
             self.remove_user_from_channel(&user, &channel);
         }
+        // otherwise the channels would keep the departed user's allocation reachable
+        for channel in self.channels.values() {
+            channel.remove_invite(&user);
+        }
         assert!(Arc::ptr_eq(&user, &self.users.remove(&nick).unwrap()));
     }
 
+    pub fn whowas(&self) -> &WhowasHistory {
+        &self.whowas
+    }
+
     pub fn _get_channel_names(&self) -> impl Iterator<Item = String> {
         self.channels.keys().cloned()
     }
@@ -102,8 +169,12 @@ impl ServerState {
     /// Returns &mut to new Channel. Panics if channel already exists.
     pub fn create_channel(&mut self, name: &str) -> SharedChannel {
         assert!(!self.channels.contains_key(name));
-        self.channels.insert(name.to_owned(), Arc::new(Channel::new(name.to_owned())));
-        Arc::clone(self.channels.get(name).unwrap())
+        let channel = Arc::new(Channel::new(name.to_owned()));
+        for mode in self.config.channel_modes.chars() {
+            channel.set_mode_type_d(mode, true);
+        }
+        self.channels.insert(name.to_owned(), Arc::clone(&channel));
+        channel
     }
 
     pub fn add_user_to_channel(&mut self, user: &SharedUser, channel: &SharedChannel) -> bool {
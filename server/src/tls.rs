@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    },
+};
+
+use crate::config::{ConfigError, TlsConfig};
+
+/// Builds an acceptor from the PEM certificate chain and private key named in the config.
+pub fn load_acceptor(tls: &TlsConfig) -> Result<TlsAcceptor, ConfigError> {
+    let certs = CertificateDer::pem_file_iter(&tls.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| ConfigError::Tls(tls.cert.clone(), e.to_string()))?;
+    let key = PrivateKeyDer::from_pem_file(&tls.key)
+        .map_err(|e| ConfigError::Tls(tls.key.clone(), e.to_string()))?;
+    let server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| ConfigError::Tls(tls.cert.clone(), e.to_string()))?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
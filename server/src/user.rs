@@ -3,21 +3,36 @@ use std::{
     collections::HashSet,
     fmt::{Debug, Display},
     hash::Hash,
+    net::IpAddr,
     sync::{Arc, Mutex, OnceLock, Weak},
+    time::SystemTime,
 };
 
-use common::message::{Command, Message, Numeric};
-use tokio::sync::mpsc;
+use common::message::{Command, MAX_LINE_LEN, Message, Numeric, pack_list};
+use tokio::sync::watch;
 
-use crate::channel::{SharedChannel, WeakChannel};
+use crate::{
+    channel::{SharedChannel, WeakChannel},
+    send_queue::SendQueue,
+};
 pub struct User {
-    tx: mpsc::Sender<Arc<Message>>,
+    queue: SendQueue,
     nickname: Mutex<String>,
     pub username: String,
     pub hostname: String,
+    /// Peer address of the connection, as given by PROXY if the listener uses it.
+    pub ip: Option<IpAddr>,
     pub realname: String,
+    /// Name of the server this user is connected to.
+    pub servername: String,
     channels: Mutex<HashSet<WeakChannel>>,
     modes: Mutex<HashSet<char>>,
+    /// Away message, if away
+    away: Mutex<Option<String>>,
+    /// Unix time of registration
+    pub signon: u64,
+    /// Unix time of the last message sent to a user or channel
+    last_active: Mutex<u64>,
 
     self_weak: OnceLock<WeakUser>,
 }
@@ -26,15 +41,20 @@ pub struct WeakUser(pub Weak<User>);
 pub type SharedUser = Arc<User>;
 
 impl User {
-    pub fn new(tx: mpsc::Sender<Arc<Message>>, hostname: String) -> User {
+    pub fn new(queue: SendQueue, ip: Option<IpAddr>, hostname: String, servername: String) -> User {
         User {
-            tx,
+            queue,
             nickname: Mutex::new(String::new()),
             username: String::new(),
             hostname,
+            ip,
             realname: String::new(),
+            servername,
             channels: Mutex::new(HashSet::new()),
             modes: Mutex::new(HashSet::new()),
+            away: Mutex::new(None),
+            signon: unix_time(),
+            last_active: Mutex::new(unix_time()),
             self_weak: OnceLock::new(),
         }
     }
@@ -92,17 +112,91 @@ impl User {
     pub fn remove_mode(&self, mode: char) -> bool {
         self.modes.lock().unwrap().remove(&mode)
     }
+    pub fn is_oper(&self) -> bool {
+        self.modes.lock().unwrap().contains(&'o')
+    }
+    pub fn is_invisible(&self) -> bool {
+        self.modes.lock().unwrap().contains(&'i')
+    }
+    /// Accounts are not implemented yet, so every user is logged out: `$a` extbans match
+    /// nobody, `$~a` matches everyone, WHOIS sends no RPL_WHOISACCOUNT and WHOX shows `0`.
+    /// Those all follow from this once accounts exist.
+    pub fn account(&self) -> Option<String> {
+        None
+    }
+
+    /* Away */
+    pub fn get_away(&self) -> Option<String> {
+        self.away.lock().unwrap().clone()
+    }
+    pub fn set_away(&self, text: Option<String>) {
+        *self.away.lock().unwrap() = text;
+    }
+    /// `G` (gone) if away, otherwise `H` (here), as shown in WHO.
+    pub fn here_or_gone(&self) -> char {
+        if self.away.lock().unwrap().is_some() { 'G' } else { 'H' }
+    }
+
+    /* Activity */
+    /// Resets the idle time shown in WHOIS.
+    pub fn mark_active(&self) {
+        *self.last_active.lock().unwrap() = unix_time();
+    }
+    pub fn idle_secs(&self) -> u64 {
+        unix_time().saturating_sub(*self.last_active.lock().unwrap())
+    }
 
     /* Messaging */
+    /// Never waits on the client. If the client has too much unread data, its connection is closed.
     pub async fn send(&self, message: Arc<Message>) {
-        let _ = self.tx.send(message).await;
+        self.queue.push(message);
+    }
+    /// The receiver holds the reason once the connection is to be closed.
+    pub fn subscribe_close(&self) -> watch::Receiver<Option<String>> {
+        self.queue.subscribe_close()
     }
+    /// Sends a numeric addressed to the user's nick, or "*" before one is set.
     /// Write all parameters after the target as one string, including the trailing ":".
     /// It will all be represented as one parameter, though it should not matter for writing.
     pub async fn reply(&self, numeric: Numeric, params: &str) {
         self.send(Arc::new(Message::new(
-            Some("akiRC.chat"), // FIXME: hardcoded servername
-            Command::Numeric(numeric, vec![self.get_nickname(), params.to_owned()]),
+            Some(&self.servername),
+            Command::Numeric(numeric, vec![self.reply_target(), params.to_owned()]),
+        )))
+        .await;
+    }
+    /// Sends `items` space-separated in "<head><items><tail>" numerics,
+    /// using as many as the line limit and `max_items` items per line need.
+    pub async fn reply_list(
+        &self,
+        numeric: Numeric,
+        head: &str,
+        items: &[String],
+        tail: &str,
+        max_items: usize,
+    ) {
+        let overhead = format!(
+            ":{} {:03} {} {}{}\r\n",
+            self.servername,
+            numeric as u16,
+            self.reply_target(),
+            head,
+            tail
+        )
+        .len();
+        for line in pack_list(items, MAX_LINE_LEN.saturating_sub(overhead), max_items) {
+            self.reply(numeric, &format!("{head}{line}{tail}")).await;
+        }
+    }
+    fn reply_target(&self) -> String {
+        let nickname = self.get_nickname();
+        if nickname.is_empty() { "*".to_string() } else { nickname }
+    }
+    /// Sends a NOTICE from the server, addressed to "*" before the user has picked a nickname.
+    pub async fn notice(&self, text: &str) {
+        self.send(Arc::new(Message::new(
+            Some(&self.servername),
+            Command::NOTICE { targets: vec![self.reply_target()], text: text.to_owned() },
         )))
         .await;
     }
@@ -123,6 +217,21 @@ impl User {
     }
 }
 
+#[cfg(test)]
+impl User {
+    /// A user named `nick` whose output can be read from the returned stream.
+    pub fn for_test(nick: &str) -> (User, tokio::io::DuplexStream) {
+        let (writer, reader) = tokio::io::duplex(1 << 16);
+        let user = User::new(SendQueue::spawn(writer, 1 << 16), None, "host".into(), "srv".into());
+        user.set_nickname(nick);
+        (user, reader)
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
+}
+
 impl Display for User {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -0,0 +1,85 @@
+// History of nicks that have left or been changed, answered by WHOWAS.
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+
+use crate::user::User;
+
+pub struct WhowasEntry {
+    pub nick: String,
+    pub username: String,
+    pub hostname: String,
+    pub realname: String,
+    pub servername: String,
+    pub signoff: DateTime<Utc>,
+}
+
+impl WhowasEntry {
+    /// Records the user as they are now.
+    pub fn new(user: &User) -> Self {
+        WhowasEntry {
+            nick: user.get_nickname(),
+            username: user.username.clone(),
+            hostname: user.hostname.clone(),
+            realname: user.realname.clone(),
+            servername: user.servername.clone(),
+            signoff: Utc::now(),
+        }
+    }
+}
+
+/// The most recent entries, newest at the front.
+#[derive(Default)]
+pub struct WhowasHistory {
+    entries: VecDeque<WhowasEntry>,
+}
+
+impl WhowasHistory {
+    /// Adds an entry, forgetting the oldest ones beyond `capacity`.
+    pub fn push(&mut self, entry: WhowasEntry, capacity: usize) {
+        self.entries.push_front(entry);
+        self.entries.truncate(capacity);
+    }
+    /// Entries for `nick`, newest first.
+    pub fn find(&self, nick: &str) -> impl Iterator<Item = &WhowasEntry> {
+        self.entries.iter().filter(move |entry| entry.nick.eq_ignore_ascii_case(nick))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(nick: &str, realname: &str) -> WhowasEntry {
+        WhowasEntry {
+            nick: nick.to_string(),
+            username: "~u".to_string(),
+            hostname: "localhost".to_string(),
+            realname: realname.to_string(),
+            servername: "irc.example.com".to_string(),
+            signoff: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let mut history = WhowasHistory::default();
+        history.push(entry("aki", "first"), 10);
+        history.push(entry("bob", "bob"), 10);
+        history.push(entry("Aki", "second"), 10);
+        let found: Vec<_> = history.find("aki").map(|entry| entry.realname.as_str()).collect();
+        assert_eq!(found, ["second", "first"]);
+        assert_eq!(history.find("nobody").count(), 0);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut history = WhowasHistory::default();
+        for realname in ["1", "2", "3"] {
+            history.push(entry("aki", realname), 2);
+        }
+        let found: Vec<_> = history.find("aki").map(|entry| entry.realname.as_str()).collect();
+        assert_eq!(found, ["3", "2"]);
+    }
+}